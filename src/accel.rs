@@ -0,0 +1,299 @@
+use std::time::Duration;
+
+// Everything that moves, the pointer and the wheel, asks one of these how fast to go.
+// `held` is the monotonic time since the key went down and the answer is in units
+// (pixels or wheel notches) per second, so the main loop can scale it by however long
+// the last tick actually took and the speed doesn't depend on the tick rate.
+pub trait AccelProfile {
+    fn speed(&self, held: Duration) -> f32;
+}
+
+// How far to move for a tick that took `dt`, in fractional units.
+pub fn distance(profile: &dyn AccelProfile, held: Duration, dt: Duration) -> f32 {
+    profile.speed(held) * dt.as_secs_f32()
+}
+
+// Carries the fractional part of the motion over to the next tick, so that the integer
+// deltas we emit add up to the exact distance, even at one pixel every few ticks.
+#[derive(Debug, Default)]
+pub struct Remainder(f32);
+
+impl Remainder {
+    pub fn take(&mut self, d: f32) -> i32 {
+        self.0 += d;
+        let whole = self.0.trunc();
+        self.0 -= whole;
+        whole as i32
+    }
+
+    pub fn reset(&mut self) {
+        self.0 = 0.0;
+    }
+}
+
+// REL_WHEEL_HI_RES counts in 1/120 of a notch. Smooth-scrolling clients read that, the
+// rest only look at REL_WHEEL, so alongside the hi-res deltas we emit a legacy notch every
+// time the hi-res total crosses 120, the same way the kernel does it for hid mice.
+#[derive(Debug, Default)]
+pub struct HiResWheel {
+    remainder: Remainder,
+    accumulated: i32,
+}
+
+impl HiResWheel {
+    // Takes a distance in notches, returns (hi-res units, legacy notches) to emit.
+    pub fn take(&mut self, notches: f32) -> (i32, i32) {
+        let hi_res = self.remainder.take(notches * 120.0);
+        // turning around starts a new notch
+        if hi_res.signum() * self.accumulated.signum() < 0 {
+            self.accumulated = 0;
+        }
+        // a fast enough profile gives distances the remainder saturates to i32::MAX
+        self.accumulated = self.accumulated.saturating_add(hi_res);
+        let legacy = self.accumulated / 120;
+        self.accumulated -= legacy * 120;
+        (hi_res, legacy)
+    }
+
+    pub fn reset(&mut self) {
+        self.remainder.reset();
+        self.accumulated = 0;
+    }
+}
+
+fn millis(d: Duration) -> f32 {
+    d.as_secs_f32() * 1000.0
+}
+
+// Acceleration Mode
+// https://en.wikipedia.org/wiki/Mouse_keys#MouseKeysAccel
+//
+// Originally defined per event, here the event count is the time held divided by the
+// interval the parameters were tuned for, and the delta per event becomes a speed.
+pub struct MouseKeysAccel {
+    pub action_delta: i32,
+    pub max_speed: i32,
+    pub time_to_max: u64,
+    pub curve: i32,
+    pub interval: u64,
+}
+
+impl AccelProfile for MouseKeysAccel {
+    fn speed(&self, held: Duration) -> f32 {
+        let i = millis(held) / self.interval as f32;
+        let action = if i >= self.time_to_max as f32 {
+            self.max_speed as f32 * self.action_delta as f32
+        } else {
+            self.action_delta as f32
+                * self.max_speed as f32
+                * ((i / self.time_to_max as f32).powf((1000.0 + self.curve as f32) / 1000.0))
+        };
+        action.max(1.0) * 1000.0 / self.interval as f32
+    }
+}
+
+/*
+ * Kinetic movement  acceleration algorithm
+ *
+ *  current speed = I + A * T/50 + A * 0.5 * T^2 | maximum B
+ *
+ * T: time since the mouse movement started
+ * E: mouse events per second (set through MOUSEKEY_INTERVAL, UHK sends 250, the
+ *    pro micro on my Signum 3.0 sends only 125!)
+ * I: initial speed at time 0
+ * A: acceleration
+ * B: base mouse travel speed
+ */
+pub struct Kinetic {
+    pub move_delta: f32,
+    pub initial_speed: f32,
+    pub base_speed: f32,
+}
+
+impl AccelProfile for Kinetic {
+    fn speed(&self, held: Duration) -> f32 {
+        let time_elapsed = millis(held) / 50.0; // 50ms resolution, as in QMK
+
+        let speed = self.initial_speed
+            + self.move_delta * time_elapsed
+            + self.move_delta * 0.5 * time_elapsed * time_elapsed;
+
+        speed.min(self.base_speed)
+    }
+}
+
+// Same speed from the first event to the last.
+pub struct Constant {
+    pub speed: f32,
+}
+
+impl AccelProfile for Constant {
+    fn speed(&self, _held: Duration) -> f32 {
+        self.speed
+    }
+}
+
+// A lookup table with linear interpolation in between the points, like libinput's custom
+// acceleration profile. Before the first point and after the last one the value stays flat.
+//
+// Stored as (milliseconds held, units per second). Curves given per event are converted
+// using the interval they were written for.
+pub struct PiecewiseLinear {
+    points: Vec<(f32, f32)>,
+}
+
+impl PiecewiseLinear {
+    // points must be sorted by their first coordinate, the config validation sees to that
+    pub fn by_tick(points: Vec<(f32, f32)>, interval: u64) -> Self {
+        let ms = interval as f32;
+        let points = points.into_iter().map(|(tick, delta)| (tick * ms, delta * 1000.0 / ms));
+        PiecewiseLinear { points: points.collect() }
+    }
+
+    pub fn by_time(points: Vec<(f32, f32)>) -> Self {
+        PiecewiseLinear { points }
+    }
+
+    fn at(&self, x: f32) -> f32 {
+        let (first, last) = match (self.points.first(), self.points.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+        if x <= first.0 {
+            return first.1;
+        }
+        if x >= last.0 {
+            return last.1;
+        }
+        let i = self.points.partition_point(|p| p.0 <= x);
+        let (x0, y0) = self.points[i - 1];
+        let (x1, y1) = self.points[i];
+        y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+    }
+}
+
+impl AccelProfile for PiecewiseLinear {
+    fn speed(&self, held: Duration) -> f32 {
+        self.at(millis(held))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3 * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn mousekeys_ramps_up_to_max_speed() {
+        let accel = MouseKeysAccel { action_delta: 1, max_speed: 12, time_to_max: 50, curve: 0, interval: 8 };
+        // at least one pixel per event from the start
+        assert!(close(accel.speed(ms(0)), 125.0));
+        // linear with curve 0: half way there after 25 events
+        assert!(close(accel.speed(ms(25 * 8)), 6.0 * 125.0));
+        assert!(close(accel.speed(ms(50 * 8)), 12.0 * 125.0));
+        assert!(close(accel.speed(ms(10_000)), 12.0 * 125.0));
+    }
+
+    #[test]
+    fn mousekeys_curve_bends_the_ramp() {
+        let linear = MouseKeysAccel { action_delta: 1, max_speed: 12, time_to_max: 50, curve: 0, interval: 8 };
+        let steep = MouseKeysAccel { curve: 1000, ..linear };
+        let half = ms(25 * 8);
+        assert!(close(steep.speed(half), 3.0 * 125.0));
+        assert!(steep.speed(half) < linear.speed(half));
+    }
+
+    #[test]
+    fn mousekeys_max_speed_does_not_overflow() {
+        let accel = MouseKeysAccel { action_delta: 100_000, max_speed: 100_000, time_to_max: 1, curve: 0, interval: 8 };
+        assert!(close(accel.speed(ms(100)), 1e10 * 125.0));
+    }
+
+    #[test]
+    fn kinetic_accelerates_until_base_speed() {
+        let kinetic = Kinetic { move_delta: 5.0, initial_speed: 100.0, base_speed: 1000.0 };
+        assert!(close(kinetic.speed(ms(0)), 100.0));
+        // T = 2: 100 + 5 * 2 + 5 * 0.5 * 4
+        assert!(close(kinetic.speed(ms(100)), 120.0));
+        assert!(close(kinetic.speed(ms(60_000)), 1000.0));
+    }
+
+    #[test]
+    fn constant_ignores_time() {
+        let constant = Constant { speed: 400.0 };
+        assert_eq!(constant.speed(ms(0)), 400.0);
+        assert_eq!(constant.speed(ms(5_000)), 400.0);
+        assert!(close(distance(&constant, ms(5_000), ms(10)), 4.0));
+    }
+
+    #[test]
+    fn piecewise_interpolates_and_stays_flat_outside() {
+        let curve = PiecewiseLinear::by_time(vec![(100.0, 200.0), (300.0, 600.0)]);
+        assert_eq!(curve.speed(ms(0)), 200.0);
+        assert!(close(curve.speed(ms(200)), 400.0));
+        assert_eq!(curve.speed(ms(1_000)), 600.0);
+        assert_eq!(PiecewiseLinear::by_time(vec![]).speed(ms(100)), 0.0);
+    }
+
+    #[test]
+    fn piecewise_by_tick_converts_to_time() {
+        // 2 pixels per event at 8ms events is 250 pixels per second
+        let curve = PiecewiseLinear::by_tick(vec![(0.0, 2.0), (10.0, 4.0)], 8);
+        assert!(close(curve.speed(ms(0)), 250.0));
+        assert!(close(curve.speed(ms(40)), 375.0));
+        assert!(close(curve.speed(ms(80)), 500.0));
+    }
+
+    #[test]
+    fn remainder_carries_the_fraction() {
+        let mut remainder = Remainder::default();
+        let moved: i32 = (0..10).map(|_| remainder.take(0.25)).sum();
+        assert_eq!(moved, 2);
+        assert_eq!(remainder.take(0.5), 1);
+        // negative motion truncates towards zero too
+        let mut remainder = Remainder::default();
+        assert_eq!(remainder.take(-0.75), 0);
+        assert_eq!(remainder.take(-0.5), -1);
+        remainder.reset();
+        assert_eq!(remainder.take(0.5), 0);
+    }
+
+    #[test]
+    fn hi_res_wheel_emits_a_notch_every_120() {
+        let mut wheel = HiResWheel::default();
+        assert_eq!(wheel.take(0.5), (60, 0));
+        assert_eq!(wheel.take(0.5), (60, 1));
+        assert_eq!(wheel.take(2.5), (300, 2));
+        assert_eq!(wheel.take(0.5), (60, 1));
+    }
+
+    #[test]
+    fn hi_res_wheel_turning_around_starts_a_new_notch() {
+        let mut wheel = HiResWheel::default();
+        assert_eq!(wheel.take(0.75), (90, 0));
+        // without the reset the 90 would cancel most of this and no notch would come
+        assert_eq!(wheel.take(-0.5), (-60, 0));
+        assert_eq!(wheel.take(-0.5), (-60, -1));
+        wheel.reset();
+        assert_eq!(wheel.take(0.5), (60, 0));
+    }
+
+    #[test]
+    fn hi_res_wheel_saturates_instead_of_overflowing() {
+        let mut wheel = HiResWheel::default();
+        assert_eq!(wheel.take(0.5), (60, 0));
+        let (hi_res, legacy) = wheel.take(1e9);
+        assert_eq!(hi_res, i32::MAX);
+        assert_eq!(legacy, i32::MAX / 120);
+        let (hi_res, legacy) = wheel.take(-1e9);
+        assert_eq!(hi_res, i32::MIN);
+        assert_eq!(legacy, i32::MIN / 120);
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use evdev::Key;
+use serde::{de, Deserialize, Deserializer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    Left,
+    Right,
+    Middle,
+    Side,
+    Extra,
+    Forward,
+    Back,
+    Task,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Left,
+        Button::Right,
+        Button::Middle,
+        Button::Side,
+        Button::Extra,
+        Button::Forward,
+        Button::Back,
+        Button::Task,
+    ];
+
+    pub fn key(self) -> Key {
+        match self {
+            Button::Left => Key::BTN_LEFT,
+            Button::Right => Key::BTN_RIGHT,
+            Button::Middle => Key::BTN_MIDDLE,
+            Button::Side => Key::BTN_SIDE,
+            Button::Extra => Key::BTN_EXTRA,
+            Button::Forward => Key::BTN_FORWARD,
+            Button::Back => Key::BTN_BACK,
+            Button::Task => Key::BTN_TASK,
+        }
+    }
+}
+
+// Where a warp puts the pointer, in screen pixels from the top left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Warp {
+    Center,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    At(u32, u32),
+}
+
+impl Warp {
+    // positions past the edge stick to it
+    pub fn position(self, width: u32, height: u32) -> (u32, u32) {
+        let (right, bottom) = (width.saturating_sub(1), height.saturating_sub(1));
+        match self {
+            Warp::Center => (width / 2, height / 2),
+            Warp::TopLeft => (0, 0),
+            Warp::TopRight => (right, 0),
+            Warp::BottomLeft => (0, bottom),
+            Warp::BottomRight => (right, bottom),
+            Warp::At(x, y) => (x.min(right), y.min(bottom)),
+        }
+    }
+}
+
+// What a source key does. In the config these are written as `kind` or `kind:argument`:
+//
+//   move:left  move:right  move:up  move:down
+//   click:left  press:left  release:left       any button, see below
+//   double-click:left  triple-click:left       timing in [click]
+//   hold:left                                  button is down while the key is
+//   drag:left                                  toggles the drag lock, `drag` alone is left
+//   scroll:up  scroll:down  scroll:left  scroll:right
+//   warp:center  warp:top-left  warp:bottom-right  warp:640,360    needs [screen]
+//   grid                                       grid navigation, keys in [grid]
+//   hints                                      hint mode, keys in [hints]
+//   mark-set  mark-jump                        the next key names the mark
+//
+// Buttons are left, right, middle, side, extra, forward, back and task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Move(Direction),
+    Click(Button, u8), // button and how many clicks
+    Press(Button),
+    Release(Button),
+    Hold(Button),
+    DragLock(Button),
+    Scroll(Direction),
+    Warp(Warp),
+    Grid,
+    Hints,
+    MarkSet,
+    MarkJump,
+}
+
+#[derive(Debug, Clone)]
+pub struct Bindings(HashMap<Key, Action>);
+
+impl Bindings {
+    pub fn get(&self, key: Key) -> Option<Action> {
+        self.0.get(&key).copied()
+    }
+
+    // warps, grid, hints and marks need the absolute device
+    pub fn uses_absolute(&self) -> bool {
+        self.0.values().any(|a| matches!(a, Action::Warp(_) | Action::Grid | Action::Hints | Action::MarkSet | Action::MarkJump))
+    }
+}
+
+impl Default for Bindings {
+    // the F13..F21 layout KMouse always had, matching the KMonad config it was written for
+    fn default() -> Self {
+        Bindings(HashMap::from([
+            (Key::KEY_F13, Action::Move(Direction::Left)),
+            (Key::KEY_F14, Action::Move(Direction::Down)),
+            (Key::KEY_F15, Action::Move(Direction::Up)),
+            (Key::KEY_F16, Action::Move(Direction::Right)),
+            (Key::KEY_F17, Action::Click(Button::Left, 1)),
+            (Key::KEY_F18, Action::Click(Button::Right, 1)),
+            (Key::KEY_F19, Action::DragLock(Button::Left)),
+            (Key::KEY_F20, Action::Scroll(Direction::Up)),
+            (Key::KEY_F21, Action::Scroll(Direction::Down)),
+        ]))
+    }
+}
+
+fn parse_direction(s: &str) -> Result<Direction, String> {
+    match s {
+        "left" => Ok(Direction::Left),
+        "right" => Ok(Direction::Right),
+        "up" => Ok(Direction::Up),
+        "down" => Ok(Direction::Down),
+        _ => Err(format!("unknown direction `{}`, expected left, right, up or down", s)),
+    }
+}
+
+fn parse_button(s: &str) -> Result<Button, String> {
+    match s {
+        "left" => Ok(Button::Left),
+        "right" => Ok(Button::Right),
+        "middle" => Ok(Button::Middle),
+        "side" => Ok(Button::Side),
+        "extra" => Ok(Button::Extra),
+        "forward" => Ok(Button::Forward),
+        "back" => Ok(Button::Back),
+        "task" => Ok(Button::Task),
+        _ => Err(format!(
+            "unknown button `{}`, expected left, right, middle, side, extra, forward, back or task",
+            s
+        )),
+    }
+}
+
+fn parse_warp(s: &str) -> Result<Warp, String> {
+    match s {
+        "center" => Ok(Warp::Center),
+        "top-left" => Ok(Warp::TopLeft),
+        "top-right" => Ok(Warp::TopRight),
+        "bottom-left" => Ok(Warp::BottomLeft),
+        "bottom-right" => Ok(Warp::BottomRight),
+        _ => {
+            let at = s.split_once(',').and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)));
+            at.map(|(x, y)| Warp::At(x, y)).ok_or_else(|| {
+                format!(
+                    "unknown warp target `{}`, expected center, top-left, top-right, bottom-left, bottom-right or x,y",
+                    s
+                )
+            })
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind.trim(), Some(arg.trim())),
+            None => (s.trim(), None),
+        };
+        let optional = arg;
+        let arg = || arg.ok_or_else(|| format!("`{}` needs an argument, e.g. `{}:left`", kind, kind));
+        let none = |action| match optional {
+            Some(arg) => Err(format!("`{}` takes no argument, got `{}`", kind, arg)),
+            None => Ok(action),
+        };
+        match kind {
+            "move" => Ok(Action::Move(parse_direction(arg()?)?)),
+            "click" => Ok(Action::Click(parse_button(arg()?)?, 1)),
+            "double-click" => Ok(Action::Click(parse_button(arg()?)?, 2)),
+            "triple-click" => Ok(Action::Click(parse_button(arg()?)?, 3)),
+            "press" => Ok(Action::Press(parse_button(arg()?)?)),
+            "release" => Ok(Action::Release(parse_button(arg()?)?)),
+            "hold" => Ok(Action::Hold(parse_button(arg()?)?)),
+            "drag" => Ok(Action::DragLock(optional.map(parse_button).transpose()?.unwrap_or(Button::Left))),
+            "scroll" => Ok(Action::Scroll(parse_direction(arg()?)?)),
+            "warp" => Ok(Action::Warp(parse_warp(arg()?)?)),
+            "grid" => none(Action::Grid),
+            "hints" => none(Action::Hints),
+            "mark-set" => none(Action::MarkSet),
+            "mark-jump" => none(Action::MarkJump),
+            _ => Err(format!(
+                "unknown action `{}`, expected one of move, click, double-click, triple-click, press, release, hold, drag, scroll, warp, grid, hints, mark-set, mark-jump",
+                kind
+            )),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Action {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+// A key written by its evdev name, KEY_F13 and the like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyName(pub Key);
+
+impl<'de> Deserialize<'de> for KeyName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Key::from_str(&s)
+            .map(KeyName)
+            .map_err(|_| de::Error::custom(format!("unknown key `{}`, expected an evdev name like KEY_F13", s)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Bindings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = HashMap::<KeyName, Action>::deserialize(deserializer)?;
+        Ok(Bindings(table.into_iter().map(|(k, a)| (k.0, a)).collect()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_takes_no_argument() {
+        assert_eq!("grid".parse(), Ok(Action::Grid));
+        assert!("grid:foo".parse::<Action>().is_err());
+        assert!("grid:".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn hints_takes_no_argument() {
+        assert_eq!("hints".parse(), Ok(Action::Hints));
+        assert!("hints:foo".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn marks_take_no_argument() {
+        assert_eq!("mark-set".parse(), Ok(Action::MarkSet));
+        assert_eq!("mark-jump".parse(), Ok(Action::MarkJump));
+        // the mark is named by the next key, not in the binding
+        assert!("mark-set:a".parse::<Action>().is_err());
+        assert!("mark-jump:a".parse::<Action>().is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use std::path::PathBuf;
+
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+
+use crate::config::DeviceConfig;
+
+/// Mouse keys for a keyboard remapped with KMonad
+#[derive(Debug, Parser)]
+#[command(name = "kmouse", version)]
+pub struct Cli {
+    /// Config file, instead of $XDG_CONFIG_HOME/kmouse/config.toml
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Print the incoming events and more detail about the devices
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+
+    /// List the input devices and exit, same as `kmouse list`
+    #[arg(long)]
+    pub list_devices: bool,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    // Cli::parse, except that the run flags before a subcommand are an error instead of
+    // getting dropped, e.g. `kmouse --device-name foo list`. clap can't reject them
+    // itself, args_conflicts_with_subcommands would take `check-config` in
+    // `kmouse --config x check-config` for the DEVICE.
+    pub fn parse_checked() -> Cli {
+        let mut command = Cli::command();
+        let matches = command.get_matches_mut();
+        if let Some((subcommand, _)) = matches.subcommand() {
+            let run_args = RunArgs::augment_args(clap::Command::new("run"));
+            let is_run_arg = |id: &clap::Id| run_args.get_arguments().any(|arg| arg.get_id() == id);
+            let given = command.get_arguments().find(|arg| {
+                is_run_arg(arg.get_id()) && matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+            });
+            if let Some(arg) = given {
+                let message = format!("`{}` only works without a subcommand or after `run`, not before `{}`", arg, subcommand);
+                command.error(ErrorKind::ArgumentConflict, message).exit();
+            }
+        }
+        Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct RunArgs {
+    /// Source device node or /dev/input/by-id link, e.g. /dev/input/event5
+    #[arg(long, value_name = "PATH")]
+    pub device: Option<PathBuf>,
+
+    /// Source device by name, `*` and `?` work as in a shell, e.g. "KMonad*"
+    #[arg(long, value_name = "NAME")]
+    pub device_name: Option<String>,
+
+    /// Source device by USB vendor and product id in hex, e.g. 1d50:615e
+    #[arg(long, value_name = "VENDOR:PRODUCT", value_parser = parse_id)]
+    pub device_id: Option<(u16, u16)>,
+
+    /// Source device by physical path, globs like --device-name
+    #[arg(long, value_name = "PHYS")]
+    pub device_phys: Option<String>,
+
+    /// Name of the virtual mouse
+    #[arg(long, value_name = "NAME", default_value = "KMouse")]
+    pub virtual_name: String,
+
+    /// Same as --device, the way older versions took it
+    #[arg(value_name = "DEVICE", conflicts_with = "device")]
+    pub device_path: Option<PathBuf>,
+}
+
+impl RunArgs {
+    // The device rule from the command line, None when it doesn't give any so the one
+    // from the config applies.
+    pub fn device_rule(&self) -> Option<DeviceConfig> {
+        let rule = DeviceConfig {
+            path: self.device.clone().or_else(|| self.device_path.clone()),
+            name: self.device_name.clone(),
+            vendor: self.device_id.map(|(vendor, _)| vendor),
+            product: self.device_id.map(|(_, product)| product),
+            phys: self.device_phys.clone(),
+        };
+        (!rule.is_empty()).then_some(rule)
+    }
+}
+
+fn parse_id(s: &str) -> Result<(u16, u16), String> {
+    let hex = |s: &str| u16::from_str_radix(s.trim_start_matches("0x"), 16);
+    match s.split_once(':') {
+        Some((vendor, product)) => match (hex(vendor), hex(product)) {
+            (Ok(vendor), Ok(product)) => Ok((vendor, product)),
+            _ => Err(format!("`{}` is not two hex numbers", s)),
+        },
+        None => Err("expected VENDOR:PRODUCT, e.g. 1d50:615e".to_string()),
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Turn the source device's keys into mouse input, the default
+    Run(RunArgs),
+    /// List the input devices
+    List,
+    /// Parse and validate the config, then exit
+    CheckConfig,
+    /// Print the acceleration curve the config gives, as time held against speed
+    Curve {
+        /// The wheel's curve instead of the pointer's
+        #[arg(long)]
+        wheel: bool,
+        /// How long a key is held, in milliseconds
+        #[arg(long, value_name = "MS", default_value_t = 1000)]
+        until: u64,
+        /// Milliseconds between rows, the configured interval by default
+        #[arg(long, value_name = "MS")]
+        step: Option<u64>,
+    },
+    /// Show the hint labels
+    Hints {
+        /// Print the label layout
+        #[arg(long, required = true)]
+        print: bool,
+    },
+}
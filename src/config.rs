@@ -0,0 +1,793 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use evdev::Key;
+use serde::Deserialize;
+
+use crate::accel::{AccelProfile, Constant, Kinetic, MouseKeysAccel, PiecewiseLinear};
+use crate::bindings::{Bindings, KeyName};
+
+// Everything that used to be a compile-time static lives here now. The file is
+// plain TOML, every key is optional and falls back to the old defaults:
+//
+//   [pointer]
+//   interval = 8
+//   max_speed = 12
+//
+//   [wheel]
+//   delay = 10
+//
+//   [bindings]
+//   KEY_F13 = "move:left"
+//   KEY_F17 = "click:left"
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub pointer: PointerConfig,
+    pub wheel: WheelConfig,
+    pub click: ClickConfig,
+    pub drag: DragConfig,
+    pub screen: Option<ScreenConfig>,
+    pub grid: GridConfig,
+    pub hints: HintsConfig,
+    pub marks: MarksConfig,
+    pub device: DeviceConfig,
+    pub bindings: Bindings,
+}
+
+// Timing of synthesized clicks. Double and triple clicks have to land inside the
+// desktop's double-click time, usually 400-500ms.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClickConfig {
+    pub press: u64, // milliseconds the button stays down
+    pub gap: u64,   // milliseconds between the clicks of a double or triple click
+}
+
+impl ClickConfig {
+    pub fn press(&self) -> Duration {
+        Duration::from_millis(self.press)
+    }
+
+    pub fn gap(&self) -> Duration {
+        Duration::from_millis(self.gap)
+    }
+}
+
+impl Default for ClickConfig {
+    fn default() -> Self {
+        ClickConfig { press: 32, gap: 32 }
+    }
+}
+
+// Size of the area the absolute device spans, the whole desktop when there are several
+// monitors. Only with this set does KMouse create that device and can warp the pointer.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScreenConfig {
+    pub width: u32,
+    pub height: u32,
+}
+
+// Keys of the grid mode, see grid.rs. The cell keys go row by row, the default is the
+// left hand block q w e / a s d / z x c.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GridConfig {
+    pub rows: u32,
+    pub columns: u32,
+    pub keys: Vec<KeyName>,
+    pub warp: KeyName,   // done, the pointer stays
+    pub click: KeyName,  // done, clicks left
+    pub cancel: KeyName,
+    pub grab: bool,      // keep the keys from reaching other programs while the grid is up
+}
+
+impl Default for GridConfig {
+    fn default() -> Self {
+        let keys = [
+            Key::KEY_Q, Key::KEY_W, Key::KEY_E,
+            Key::KEY_A, Key::KEY_S, Key::KEY_D,
+            Key::KEY_Z, Key::KEY_X, Key::KEY_C,
+        ];
+        GridConfig {
+            rows: 3,
+            columns: 3,
+            keys: keys.into_iter().map(KeyName).collect(),
+            warp: KeyName(Key::KEY_ENTER),
+            click: KeyName(Key::KEY_SPACE),
+            cancel: KeyName(Key::KEY_ESC),
+            grab: true,
+        }
+    }
+}
+
+// Hint mode, see hints.rs. Labels are two letters from the alphabet, so it needs at
+// least as many letters as the square root of the cell count.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HintsConfig {
+    pub alphabet: String,
+    pub rows: u32,
+    pub columns: u32,
+    pub click: KeyName,  // clicks left where the last label put the pointer, and exits
+    pub cancel: KeyName, // exits, the pointer stays
+    pub grab: bool,
+    pub socket: Option<PathBuf>, // where an overlay can connect to draw the labels
+}
+
+impl Default for HintsConfig {
+    fn default() -> Self {
+        HintsConfig {
+            alphabet: "asdfghjklqwertyuiopzxcvbnm".to_string(),
+            rows: 9,
+            columns: 16,
+            click: KeyName(Key::KEY_SPACE),
+            cancel: KeyName(Key::KEY_ESC),
+            grab: true,
+            socket: None,
+        }
+    }
+}
+
+// Which input device to read, see devices.rs. Every rule given has to match, names and
+// phys paths can use `*` and `?`. With no rule at all it's the KMonad output device.
+//
+//   [device]
+//   name = "KMonad*"
+//   vendor = 0x1d50
+//   product = 0x615e
+//   phys = "usb-0000:00:14.0-3/input0"
+//   path = "/dev/input/by-id/usb-Keyboardio_Model_01-event-kbd"
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub path: Option<PathBuf>, // device node or a link to it, like the ones in /dev/input/by-id
+    pub name: Option<String>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+    pub phys: Option<String>,
+}
+
+impl DeviceConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == DeviceConfig::default()
+    }
+}
+
+// Marks, see marks.rs. After mark-set or mark-jump the next key press names the mark.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarksConfig {
+    pub cancel: KeyName,
+    pub grab: bool, // keep the naming key from reaching other programs
+    pub file: Option<PathBuf>, // defaults to $XDG_STATE_HOME/kmouse/marks
+}
+
+impl Default for MarksConfig {
+    fn default() -> Self {
+        MarksConfig { cancel: KeyName(Key::KEY_ESC), grab: true, file: None }
+    }
+}
+
+// When a drag lock lets go on its own, see drag.rs for how it interacts with clicks.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DragConfig {
+    pub timeout: u64,           // milliseconds without motion or key presses, 0 never
+    pub release_on_click: bool, // a click of any other button ends every lock first
+}
+
+impl DragConfig {
+    pub fn timeout(&self) -> Option<Duration> {
+        (self.timeout > 0).then(|| Duration::from_millis(self.timeout))
+    }
+}
+
+// Which AccelProfile drives the motion, see accel.rs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccelMode {
+    MouseKeys,
+    Kinetic,
+    Constant,
+    Custom,
+}
+
+// What the first coordinate of a custom curve point is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PointsBy {
+    Tick, // (event count, units per event)
+    Time, // (milliseconds held, units per second)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WheelAccelMode {
+    MouseKeys,
+    Constant,
+    Custom,
+}
+
+// What to do when both keys of an axis are held, e.g. left and right
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Opposite {
+    Cancel, // no motion along that axis
+    Last,   // the key pressed last wins
+}
+
+// What a change of direction does to the acceleration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnTurn {
+    Keep,  // keep the current speed
+    Reset, // start the ramp over
+}
+
+// Acceleration Mode
+// https://en.wikipedia.org/wiki/Mouse_keys#MouseKeysAccel
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PointerConfig {
+    pub accel: AccelMode,    // mousekeys, kinetic (uses [pointer.kinetic]), constant or custom
+    pub action_delta: i32,   // pixels moved per action_delta unit
+    pub delay: u64,          // milliseconds between the initial key press and first repeated motion event
+    pub interval: u64,       // milliseconds between repeated motion events
+    pub max_speed: i32,      // steady speed (in action_delta units) applied each event
+    pub time_to_max: u64,    // number of intervals accelerating to steady speed
+    pub curve: i32,          // ramp used to reach maximum pointer speed
+    pub speed: f32,          // constant: pixels per second
+    pub points: Vec<[f32; 2]>, // custom: curve points, see points_by
+    pub points_by: PointsBy,   // custom: `tick` or `time`
+    pub opposite: Opposite,  // cancel or last
+    pub on_turn: OnTurn,     // keep or reset
+    pub kinetic: KineticConfig,
+}
+
+impl PointerConfig {
+    // the timing knobs belong to whichever mode is active
+    pub fn delay(&self) -> u64 {
+        match self.accel {
+            AccelMode::Kinetic => self.kinetic.delay,
+            _ => self.delay,
+        }
+    }
+
+    pub fn interval(&self) -> u64 {
+        match self.accel {
+            AccelMode::Kinetic => self.kinetic.interval,
+            _ => self.interval,
+        }
+    }
+
+    pub fn profile(&self) -> Box<dyn AccelProfile> {
+        match self.accel {
+            AccelMode::MouseKeys => Box::new(MouseKeysAccel {
+                action_delta: self.action_delta,
+                max_speed: self.max_speed,
+                time_to_max: self.time_to_max,
+                curve: self.curve,
+                interval: self.interval,
+            }),
+            AccelMode::Kinetic => Box::new(Kinetic {
+                move_delta: self.kinetic.move_delta,
+                initial_speed: self.kinetic.initial_speed,
+                base_speed: self.kinetic.base_speed,
+            }),
+            AccelMode::Constant => Box::new(Constant { speed: self.speed }),
+            AccelMode::Custom => custom_curve(&self.points, self.points_by, self.interval),
+        }
+    }
+}
+
+impl Default for PointerConfig {
+    fn default() -> Self {
+        PointerConfig {
+            accel: AccelMode::MouseKeys,
+            action_delta: 7,
+            delay: 32,
+            interval: 8,
+            max_speed: 10,
+            time_to_max: 120,
+            curve: 500,
+            speed: 1000.0,
+            points: vec![[0.0, 1.0], [120.0, 70.0]],
+            points_by: PointsBy::Tick,
+            opposite: Opposite::Cancel,
+            on_turn: OnTurn::Keep,
+            kinetic: KineticConfig::default(),
+        }
+    }
+}
+
+// Kinetic mode
+// https://docs.qmk.fm/#/feature_mouse_keys?id=kinetic-mode
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KineticConfig {
+    pub delay: u64,         // delay between pressing a movement key and cursor movement
+    pub interval: u64,      // time between cursor movements in milliseconds
+    pub move_delta: f32,    // step size for accelerating from initial to base speed
+    pub initial_speed: f32, // initial speed of the cursor in pixel per second
+    pub base_speed: f32,    // maximum cursor speed at which acceleration stops
+}
+
+impl Default for KineticConfig {
+    fn default() -> Self {
+        KineticConfig {
+            delay: 5,
+            interval: 10,
+            move_delta: 16.0,
+            initial_speed: 100.0,
+            base_speed: 5000.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WheelConfig {
+    pub accel: WheelAccelMode, // mousekeys, constant or custom
+    pub delta: i32,            // wheel notches per delta unit
+    pub delay: u64,            // milliseconds between the initial key press and first repeated wheel event
+    pub interval: u64,         // milliseconds between repeated wheel events
+    pub max_speed: i32,        // steady speed (in delta units) applied each event
+    pub time_to_max: u64,      // number of intervals accelerating to steady speed
+    pub curve: i32,            // ramp used to reach maximum wheel speed
+    pub speed: f32,            // constant: notches per second
+    pub points: Vec<[f32; 2]>, // custom: curve points, see points_by
+    pub points_by: PointsBy,   // custom: `tick` or `time`
+}
+
+impl WheelConfig {
+    pub fn profile(&self) -> Box<dyn AccelProfile> {
+        match self.accel {
+            WheelAccelMode::MouseKeys => Box::new(MouseKeysAccel {
+                action_delta: self.delta,
+                max_speed: self.max_speed,
+                time_to_max: self.time_to_max,
+                curve: self.curve,
+                interval: self.interval,
+            }),
+            WheelAccelMode::Constant => Box::new(Constant { speed: self.speed }),
+            WheelAccelMode::Custom => custom_curve(&self.points, self.points_by, self.interval),
+        }
+    }
+}
+
+fn custom_curve(points: &[[f32; 2]], by: PointsBy, interval: u64) -> Box<dyn AccelProfile> {
+    let points = points.iter().map(|p| (p[0], p[1])).collect();
+    match by {
+        PointsBy::Tick => Box::new(PiecewiseLinear::by_tick(points, interval)),
+        PointsBy::Time => Box::new(PiecewiseLinear::by_time(points)),
+    }
+}
+
+impl Default for WheelConfig {
+    fn default() -> Self {
+        WheelConfig {
+            accel: WheelAccelMode::MouseKeys,
+            delta: 3,
+            delay: 10,
+            interval: 80,
+            max_speed: 8,
+            time_to_max: 80,
+            curve: 500,
+            speed: 12.5,
+            points: vec![[0.0, 1.0], [80.0, 24.0]],
+            points_by: PointsBy::Tick,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, ": `{}`", key)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Looks for `kmouse/config.toml` in `$XDG_CONFIG_HOME` (or `~/.config`) and then in
+/// `$XDG_CONFIG_DIRS` (or `/etc/xdg`). Returns the first file that exists.
+pub fn find_config() -> Option<PathBuf> {
+    let mut dirs = vec![];
+    match std::env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        Some(dir) => dirs.push(PathBuf::from(dir)),
+        None => {
+            if let Some(home) = std::env::var_os("HOME") {
+                dirs.push(Path::new(&home).join(".config"));
+            }
+        }
+    }
+    let system_dirs = std::env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".to_string());
+    dirs.extend(system_dirs.split(':').filter(|d| !d.is_empty()).map(PathBuf::from));
+
+    dirs.into_iter()
+        .map(|d| d.join("kmouse").join("config.toml"))
+        .find(|p| p.is_file())
+}
+
+/// Loads the config from `path`, or from the XDG location when `path` is `None`.
+/// No config file at all is not an error, the defaults are used then.
+pub fn load(path: Option<&Path>) -> Result<(Config, Option<PathBuf>), ConfigError> {
+    let path = match path {
+        Some(p) => p.to_path_buf(),
+        None => match find_config() {
+            Some(p) => p,
+            None => return Ok((Config::default(), None)),
+        },
+    };
+    let source = std::fs::read_to_string(&path).map_err(|e| ConfigError {
+        path: path.clone(),
+        line: None,
+        key: None,
+        message: e.to_string(),
+    })?;
+    let config = parse(&path, &source)?;
+    Ok((config, Some(path)))
+}
+
+pub fn parse(path: &Path, source: &str) -> Result<Config, ConfigError> {
+    let config: Config = toml::from_str(source).map_err(|e| {
+        let offset = e.span().map(|s| s.start);
+        ConfigError {
+            path: path.to_path_buf(),
+            line: offset.map(|o| line_at(source, o)),
+            key: offset.and_then(|o| key_at(source, o)),
+            message: e.message().to_string(),
+        }
+    })?;
+    validate(&config).map_err(|(section, key, message)| ConfigError {
+        path: path.to_path_buf(),
+        line: find_key(source, section, key),
+        key: Some(format!("{}.{}", section, key)),
+        message,
+    })?;
+    Ok(config)
+}
+
+const MAX_SPEED: f32 = 1_000_000.0;
+// rows or columns of the grid, there aren't that many keys to give the cells anyway
+const MAX_GRID: u32 = 100;
+
+fn validate(config: &Config) -> Result<(), (&'static str, &'static str, String)> {
+    let p = &config.pointer;
+    let w = &config.wheel;
+    let k = &p.kinetic;
+    let positive: [(&str, &str, i64); 11] = [
+        ("pointer", "action_delta", p.action_delta as i64),
+        ("pointer", "interval", p.interval as i64),
+        ("pointer", "max_speed", p.max_speed as i64),
+        ("pointer", "time_to_max", p.time_to_max as i64),
+        ("wheel", "delta", w.delta as i64),
+        ("wheel", "interval", w.interval as i64),
+        ("wheel", "max_speed", w.max_speed as i64),
+        ("wheel", "time_to_max", w.time_to_max as i64),
+        ("pointer.kinetic", "interval", k.interval as i64),
+        ("click", "press", config.click.press as i64),
+        ("click", "gap", config.click.gap as i64),
+    ];
+    for (section, key, value) in positive {
+        if value <= 0 {
+            return Err((section, key, format!("must be greater than 0, got {}", value)));
+        }
+    }
+    // units per second, a million pixels or notches is already far past any screen
+    for (key, value) in [("move_delta", k.move_delta), ("initial_speed", k.initial_speed)] {
+        if !(0.0..=MAX_SPEED).contains(&value) {
+            return Err(("pointer.kinetic", key, format!("must be between 0 and {}, got {}", MAX_SPEED, value)));
+        }
+    }
+    if !k.base_speed.is_finite() || k.base_speed <= 0.0 || k.base_speed > MAX_SPEED {
+        return Err((
+            "pointer.kinetic",
+            "base_speed",
+            format!("must be greater than 0 and at most {}, got {}", MAX_SPEED, k.base_speed),
+        ));
+    }
+    for (section, speed) in [("pointer", p.speed), ("wheel", w.speed)] {
+        if !speed.is_finite() || speed <= 0.0 || speed > MAX_SPEED {
+            return Err((section, "speed", format!("must be greater than 0 and at most {}, got {}", MAX_SPEED, speed)));
+        }
+    }
+    let mousekeys = [
+        ("pointer", p.action_delta, p.max_speed, p.interval),
+        ("wheel", w.delta, w.max_speed, w.interval),
+    ];
+    for (section, delta, max_speed, interval) in mousekeys {
+        let top = delta as f64 * max_speed as f64 * 1000.0 / interval as f64;
+        if top > MAX_SPEED as f64 {
+            return Err((
+                section,
+                "max_speed",
+                format!("{} times {} every {}ms is {:.0} per second, at most {} is allowed", max_speed, delta, interval, top, MAX_SPEED),
+            ));
+        }
+    }
+    let custom = [
+        ("pointer", &p.points, p.points_by, p.interval),
+        ("wheel", &w.points, w.points_by, w.interval),
+    ];
+    for (section, points, by, interval) in custom {
+        // by tick the values are per event
+        let max = match by {
+            PointsBy::Time => MAX_SPEED,
+            PointsBy::Tick => MAX_SPEED * interval as f32 / 1000.0,
+        };
+        validate_points(points, max).map_err(|e| (section, "points", e))?;
+    }
+    match &config.screen {
+        Some(screen) => {
+            // the absolute axes are i32
+            for (key, value) in [("width", screen.width), ("height", screen.height)] {
+                if value == 0 || value > i32::MAX as u32 {
+                    return Err(("screen", key, format!("must be between 1 and {}, got {}", i32::MAX, value)));
+                }
+            }
+        }
+        None if config.bindings.uses_absolute() => {
+            return Err(("screen", "width", "warp, grid, hints and mark bindings need a [screen] with width and height".to_string()));
+        }
+        None => {}
+    }
+    let g = &config.grid;
+    for (key, value) in [("rows", g.rows), ("columns", g.columns)] {
+        if value == 0 || value > MAX_GRID {
+            return Err(("grid", key, format!("must be between 1 and {}, got {}", MAX_GRID, value)));
+        }
+    }
+    let cells = g.rows as u64 * g.columns as u64;
+    if g.keys.len() as u64 != cells {
+        return Err((
+            "grid",
+            "keys",
+            format!("needs one key per cell, {} for {}x{}, got {}", cells, g.rows, g.columns, g.keys.len()),
+        ));
+    }
+    let h = &config.hints;
+    for (key, value) in [("rows", h.rows), ("columns", h.columns)] {
+        if value == 0 {
+            return Err(("hints", key, "must be greater than 0, got 0".to_string()));
+        }
+    }
+    for (i, c) in h.alphabet.chars().enumerate() {
+        if !c.is_ascii_lowercase() {
+            return Err(("hints", "alphabet", format!("`{}` is not a letter from a to z", c)));
+        }
+        if h.alphabet.chars().take(i).any(|prev| prev == c) {
+            return Err(("hints", "alphabet", format!("`{}` appears twice", c)));
+        }
+    }
+    let letters = h.alphabet.len() as u64;
+    let cells = h.rows as u64 * h.columns as u64;
+    if letters * letters < cells {
+        return Err((
+            "hints",
+            "alphabet",
+            format!("{} letters make {} labels, {}x{} needs {}", letters, letters * letters, h.rows, h.columns, cells),
+        ));
+    }
+    // the exponent is (1000 + curve) / 1000, anything at or below -1000 flattens the ramp
+    for (section, curve) in [("pointer", p.curve), ("wheel", w.curve)] {
+        if curve <= -1000 {
+            return Err((section, "curve", format!("must be greater than -1000, got {}", curve)));
+        }
+    }
+    Ok(())
+}
+
+fn validate_points(points: &[[f32; 2]], max: f32) -> Result<(), String> {
+    if points.len() < 2 {
+        return Err("needs at least two points".to_string());
+    }
+    for (i, p) in points.iter().enumerate() {
+        if !p[0].is_finite() || !p[1].is_finite() || p[0] < 0.0 || p[1] < 0.0 {
+            return Err(format!("point {} ({}, {}) must not be negative", i + 1, p[0], p[1]));
+        }
+        if p[1] > max {
+            return Err(format!("point {} ({}, {}) is too fast, at most {} is allowed", i + 1, p[0], p[1], max));
+        }
+        if i > 0 && p[0] <= points[i - 1][0] {
+            return Err(format!("point {} ({}, {}) is not after the previous one", i + 1, p[0], p[1]));
+        }
+    }
+    Ok(())
+}
+
+fn line_at(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+fn line_start(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].rfind('\n').map(|i| i + 1).unwrap_or(0)
+}
+
+fn section_header(line: &str) -> Option<&str> {
+    let line = line.trim();
+    line.strip_prefix('[')
+        .and_then(|l| l.split(']').next())
+        .map(|s| s.trim_matches(|c| c == '[' || c == ' '))
+        // not a line of a multi-line array, like `[0, 1],`
+        .filter(|s| s.chars().all(|c| c.is_alphanumeric() || "_-.\" ".contains(c)))
+}
+
+// `a . "b"` and `a.b` are the same key
+fn key_of(line: &str) -> Option<String> {
+    let (key, _) = line.split_once('=')?;
+    let parts: Vec<&str> = key.split('.').map(|p| p.trim().trim_matches('"')).collect();
+    Some(parts.join("."))
+}
+
+// Reconstructs the dotted key for an error offset by looking at the line it points into
+// and the closest table header above it.
+fn key_at(source: &str, offset: usize) -> Option<String> {
+    let start = line_start(source, offset);
+    let line = source[start..].lines().next().unwrap_or("");
+    if let Some(header) = section_header(line) {
+        return Some(header.to_string());
+    }
+    let key = key_of(line)?;
+    let section = source[..start].lines().rev().find_map(section_header);
+    Some(match section {
+        Some(s) => format!("{}.{}", s, key),
+        None => key,
+    })
+}
+
+// The line setting `section.key`, under a `[section]` header or written out dotted,
+// like `pointer.interval = 8` at the top or `kinetic.delay = 5` under `[pointer]`.
+fn find_key(source: &str, section: &str, key: &str) -> Option<usize> {
+    let wanted = format!("{}.{}", section, key);
+    let mut current = None;
+    for (n, line) in source.lines().enumerate() {
+        if let Some(header) = section_header(line) {
+            current = Some(header);
+        } else if let Some(k) = key_of(line) {
+            let full = match current {
+                Some(c) => format!("{}.{}", c, k),
+                None => k,
+            };
+            if full == wanted {
+                return Some(n + 1);
+            }
+        }
+    }
+    // fall back to the table header, so the user at least knows where to add it
+    source
+        .lines()
+        .position(|l| section_header(l) == Some(section))
+        .map(|n| n + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> ConfigError {
+        parse(Path::new("config.toml"), source).unwrap_err()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(parse(Path::new("config.toml"), "").is_ok());
+    }
+
+    #[test]
+    fn type_error_names_key_and_line() {
+        let e = error("[wheel]\ndelay = 10\n\n[pointer]\ninterval = \"fast\"\n");
+        assert_eq!(e.line, Some(5));
+        assert_eq!(e.key.as_deref(), Some("pointer.interval"));
+    }
+
+    #[test]
+    fn unknown_field_names_key_and_line() {
+        let e = error("[pointer]\nspeed = 100\nspeeed = 3\n");
+        assert_eq!(e.line, Some(3));
+        assert_eq!(e.key.as_deref(), Some("pointer.speeed"));
+        assert!(e.message.contains("unknown field"));
+    }
+
+    #[test]
+    fn bad_binding_names_key_and_line() {
+        let e = error("[bindings]\nKEY_F13 = \"move:left\"\nKEY_F14 = \"move:sideways\"\n");
+        assert_eq!(e.line, Some(3));
+        assert_eq!(e.key.as_deref(), Some("bindings.KEY_F14"));
+        let e = error("[bindings]\nKEY_NOPE = \"move:left\"\n");
+        assert_eq!(e.line, Some(2));
+        assert_eq!(e.key.as_deref(), Some("bindings.KEY_NOPE"));
+    }
+
+    #[test]
+    fn validate_failure_names_key_and_line() {
+        let e = error("[grid]\ncolumns = 3\nrows = 0\n");
+        assert_eq!(e.line, Some(3));
+        assert_eq!(e.key.as_deref(), Some("grid.rows"));
+        // the same key in another section doesn't count
+        let e = error("[hints]\nrows = 0\n\n[grid]\nrows = 3\n");
+        assert_eq!(e.line, Some(2));
+        assert_eq!(e.key.as_deref(), Some("hints.rows"));
+    }
+
+    #[test]
+    fn validate_failure_with_dotted_keys() {
+        let e = error("pointer.interval = 0\n");
+        assert_eq!(e.line, Some(1));
+        assert_eq!(e.key.as_deref(), Some("pointer.interval"));
+        let e = error("[pointer]\ndelay = 5\nkinetic.interval = 0\n");
+        assert_eq!(e.line, Some(3));
+        assert_eq!(e.key.as_deref(), Some("pointer.kinetic.interval"));
+    }
+
+    #[test]
+    fn validate_failure_without_the_key_points_at_the_section() {
+        // the missing [screen] is reported where it would go, nowhere here
+        let e = error("[bindings]\nKEY_F13 = \"warp:center\"\n");
+        assert_eq!(e.key.as_deref(), Some("screen.width"));
+        assert_eq!(e.line, None);
+        // arrays spread over lines aren't table headers
+        let e = error("[pointer]\npoints = [\n  [0, 1],\n  [0, 2],\n]\n");
+        assert_eq!(e.line, Some(2));
+        assert_eq!(e.key.as_deref(), Some("pointer.points"));
+    }
+
+    #[test]
+    fn rejects_mousekeys_speeds_that_overflow() {
+        let e = error("[pointer]\nmax_speed = 100000\naction_delta = 100000\n");
+        assert_eq!(e.key.as_deref(), Some("pointer.max_speed"));
+        assert_eq!(e.line, Some(2));
+        let e = error("[wheel]\ndelta = 100000\nmax_speed = 10000\n");
+        assert_eq!(e.key.as_deref(), Some("wheel.max_speed"));
+    }
+
+    #[test]
+    fn rejects_infinite_kinetic_speeds() {
+        for key in ["base_speed", "initial_speed", "move_delta"] {
+            let e = error(&format!("[pointer.kinetic]\n{} = inf\n", key));
+            assert_eq!(e.key, Some(format!("pointer.kinetic.{}", key)));
+        }
+        let e = error("[pointer.kinetic]\nbase_speed = 1e38\n");
+        assert_eq!(e.key.as_deref(), Some("pointer.kinetic.base_speed"));
+    }
+
+    #[test]
+    fn rejects_constant_speeds_out_of_range() {
+        for speed in ["inf", "1e38", "0", "nan"] {
+            let e = error(&format!("[wheel]\nspeed = {}\n", speed));
+            assert_eq!(e.key.as_deref(), Some("wheel.speed"));
+        }
+    }
+
+    #[test]
+    fn rejects_custom_points_too_fast() {
+        let e = error("[pointer]\npoints_by = \"time\"\npoints = [[0, 1e38], [10, 3e38]]\n");
+        assert_eq!(e.key.as_deref(), Some("pointer.points"));
+        assert_eq!(e.line, Some(3));
+        // per event at 8ms that is 125 events a second
+        let e = error("[pointer]\npoints = [[0, 1], [10, 10000]]\n");
+        assert_eq!(e.key.as_deref(), Some("pointer.points"));
+        assert!(parse(Path::new("config.toml"), "[pointer]\npoints = [[0, 1], [10, 7000]]\n").is_ok());
+    }
+}
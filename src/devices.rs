@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+
+use evdev::Device;
+
+use crate::config::DeviceConfig;
+
+// evdev::enumerate returns them in reverse order from their eventN names, readdir order
+fn enumerate() -> Vec<(PathBuf, Device)> {
+    let mut devices: Vec<_> = evdev::enumerate().collect();
+    devices.sort_by_key(|(path, _)| event_number(path));
+    devices
+}
+
+fn event_number(path: &Path) -> Option<u32> {
+    path.file_name()?.to_str()?.strip_prefix("event")?.parse().ok()
+}
+
+fn describe(path: &Path, device: &Device, verbose: bool) -> String {
+    let name = device.name().unwrap_or("Unnamed device");
+    if verbose {
+        let id = device.input_id();
+        format!(
+            "{}: {} [{:04x}:{:04x}] {}",
+            path.display(),
+            name,
+            id.vendor(),
+            id.product(),
+            device.physical_path().unwrap_or("")
+        )
+    } else {
+        format!("{}: {}", path.display(), name)
+    }
+}
+
+// kmouse list
+pub fn list(verbose: bool) {
+    for (path, device) in enumerate() {
+        println!("{}", describe(&path, &device, verbose));
+    }
+}
+
+pub fn candidates() -> String {
+    let devices: Vec<String> = enumerate().iter().map(|(p, d)| format!("  {}", describe(p, d, true))).collect();
+    if devices.is_empty() {
+        "No input devices are readable, is kmouse in the input group?".to_string()
+    } else {
+        format!("Available devices:\n{}", devices.join("\n"))
+    }
+}
+
+// Shell style `*` and `?`, the whole string has to match.
+fn glob(pattern: &str, s: &str) -> bool {
+    let (p, s): (Vec<char>, Vec<char>) = (pattern.chars().collect(), s.chars().collect());
+    // where to resume after the last `*`: its position in the pattern and in the string
+    let mut star: Option<(usize, usize)> = None;
+    let (mut i, mut j) = (0, 0);
+    while j < s.len() {
+        if i < p.len() && (p[i] == '?' || p[i] == s[j]) {
+            i += 1;
+            j += 1;
+        } else if i < p.len() && p[i] == '*' {
+            star = Some((i, j));
+            i += 1;
+        } else if let Some((si, sj)) = star {
+            // let the `*` take one more character
+            star = Some((si, sj + 1));
+            i = si + 1;
+            j = sj + 1;
+        } else {
+            return false;
+        }
+    }
+    p[i..].iter().all(|c| *c == '*')
+}
+
+fn matches(rule: &DeviceConfig, path: &Path, device: &Device) -> bool {
+    let id = device.input_id();
+    // links like /dev/input/by-id/... are compared by what they point to
+    let same_node = |p: &Path| match (p.canonicalize(), path.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    };
+    rule.path.as_deref().is_none_or(same_node)
+        && rule.name.as_deref().is_none_or(|n| glob(n, device.name().unwrap_or("")))
+        && rule.vendor.is_none_or(|v| v == id.vendor())
+        && rule.product.is_none_or(|p| p == id.product())
+        && rule.phys.as_deref().is_none_or(|p| glob(p, device.physical_path().unwrap_or("")))
+}
+
+// Every readable device the rule matches, in eventN order.
+pub fn find(rule: &DeviceConfig) -> Vec<(PathBuf, Device)> {
+    enumerate().into_iter().filter(|(p, d)| matches(rule, p, d)).collect()
+}
+
+// The device at a node, if it's readable and the rule matches it.
+pub fn open(rule: &DeviceConfig, path: &Path) -> Option<Device> {
+    // not a mouseN or jsN node
+    event_number(path)?;
+    let device = Device::open(path).ok()?;
+    matches(rule, path, &device).then_some(device)
+}
+
+pub fn describe_rule(rule: &DeviceConfig) -> String {
+    let mut parts = vec![];
+    if let Some(path) = &rule.path {
+        parts.push(format!("path {}", path.display()));
+    }
+    if let Some(name) = &rule.name {
+        parts.push(format!("name `{}`", name));
+    }
+    if let Some(vendor) = rule.vendor {
+        parts.push(format!("vendor {:04x}", vendor));
+    }
+    if let Some(product) = rule.product {
+        parts.push(format!("product {:04x}", product));
+    }
+    if let Some(phys) = &rule.phys {
+        parts.push(format!("phys `{}`", phys));
+    }
+    parts.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob;
+
+    #[test]
+    fn glob_without_wildcards_matches_the_whole_string() {
+        assert!(glob("KMonad output", "KMonad output"));
+        assert!(!glob("KMonad", "KMonad output"));
+        assert!(!glob("KMonad output", "KMonad"));
+    }
+
+    #[test]
+    fn glob_star_at_either_end() {
+        assert!(glob("KMonad*", "KMonad output"));
+        assert!(glob("KMonad*", "KMonad"));
+        assert!(glob("*output", "KMonad output"));
+        assert!(glob("*", ""));
+        assert!(glob("**", "anything"));
+        assert!(!glob("*output", "KMonad output 2"));
+    }
+
+    #[test]
+    fn glob_question_mark_is_one_character() {
+        assert!(glob("event?", "event5"));
+        assert!(!glob("event?", "event"));
+        assert!(!glob("event?", "event15"));
+        assert!(glob("event??", "event15"));
+    }
+
+    #[test]
+    fn glob_empty_pattern_only_matches_empty() {
+        assert!(glob("", ""));
+        assert!(!glob("", "a"));
+    }
+
+    #[test]
+    fn glob_star_backtracks() {
+        assert!(glob("a*b*c", "axbxbc"));
+        assert!(glob("a*bc", "abcbc"));
+        assert!(!glob("a*b*c", "axbxbcx"));
+        assert!(!glob("a*b*c", "axcxc"));
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::bindings::Button;
+
+// Buttons held down by a drag lock. Every button has its own lock, along with the last
+// time anything happened while it was held, so an idle lock can let go on its own.
+//
+// How clicks interact with a lock:
+// - clicking the locked button ends the drag, it releases and doesn't click again
+// - clicking another button clicks it while the lock stays, e.g. a right click in the
+//   middle of a drag, unless `release_on_click` is set, then all locks go first
+// - releasing the button in any other way (release:, hold:) ends its lock too
+#[derive(Default)]
+pub struct DragLocks {
+    locked: HashMap<Button, Instant>,
+}
+
+impl DragLocks {
+    pub fn lock(&mut self, button: Button) {
+        self.locked.insert(button, Instant::now());
+    }
+
+    // Returns whether the button was locked.
+    pub fn unlock(&mut self, button: Button) -> bool {
+        self.locked.remove(&button).is_some()
+    }
+
+    pub fn unlock_all(&mut self) -> Vec<Button> {
+        self.locked.drain().map(|(b, _)| b).collect()
+    }
+
+    // Something happened: the pointer moved, a bound key was pressed.
+    pub fn touch(&mut self) {
+        let now = Instant::now();
+        for last in self.locked.values_mut() {
+            *last = now;
+        }
+    }
+
+    // Resolves when a lock has been idle for `timeout`, never without one.
+    pub async fn idle(&self, timeout: Option<Duration>) {
+        match (timeout, self.locked.values().min()) {
+            (Some(timeout), Some(last)) => tokio::time::sleep_until((*last + timeout).into()).await,
+            _ => std::future::pending().await,
+        }
+    }
+
+    pub fn unlock_idle(&mut self, timeout: Duration) -> Vec<Button> {
+        let now = Instant::now();
+        let idle: Vec<Button> = self
+            .locked
+            .iter()
+            .filter(|(_, last)| now - **last >= timeout)
+            .map(|(b, _)| *b)
+            .collect();
+        for b in &idle {
+            self.locked.remove(b);
+        }
+        idle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // as if the last activity was `ago`
+    fn age(locks: &mut DragLocks, button: Button, ago: Duration) {
+        locks.locked.insert(button, Instant::now() - ago);
+    }
+
+    #[test]
+    fn unlock_reports_whether_it_was_locked() {
+        let mut locks = DragLocks::default();
+        assert!(!locks.unlock(Button::Left));
+        locks.lock(Button::Left);
+        assert!(locks.unlock(Button::Left));
+        assert!(!locks.unlock(Button::Left));
+    }
+
+    #[test]
+    fn unlock_all_lets_go_of_every_button() {
+        let mut locks = DragLocks::default();
+        locks.lock(Button::Left);
+        locks.lock(Button::Middle);
+        let mut all = locks.unlock_all();
+        all.sort_by_key(|b| b.key().0);
+        assert_eq!(all, [Button::Left, Button::Middle]);
+        assert!(locks.unlock_all().is_empty());
+        assert!(!locks.unlock(Button::Left));
+    }
+
+    #[test]
+    fn unlock_idle_only_takes_idle_locks() {
+        let timeout = Duration::from_secs(5);
+        let mut locks = DragLocks::default();
+        age(&mut locks, Button::Left, Duration::from_secs(10));
+        age(&mut locks, Button::Right, Duration::from_secs(1));
+        assert_eq!(locks.unlock_idle(timeout), [Button::Left]);
+        assert!(locks.unlock_idle(timeout).is_empty());
+        assert!(locks.unlock(Button::Right));
+    }
+
+    #[test]
+    fn touch_keeps_locks_from_going_idle() {
+        let timeout = Duration::from_secs(5);
+        let mut locks = DragLocks::default();
+        age(&mut locks, Button::Left, Duration::from_secs(10));
+        locks.touch();
+        assert!(locks.unlock_idle(timeout).is_empty());
+        assert!(locks.unlock(Button::Left));
+    }
+}
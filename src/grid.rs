@@ -0,0 +1,55 @@
+use evdev::Key;
+
+use crate::bindings::Warp;
+use crate::config::{GridConfig, ScreenConfig};
+
+// What a key does while the grid is up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridKey {
+    Cell(u32, u32), // row and column
+    Warp,           // leave the pointer where it is and exit
+    Click,          // click left there and exit
+    Cancel,
+}
+
+impl GridConfig {
+    pub fn key(&self, key: Key) -> Option<GridKey> {
+        if let Some(i) = self.keys.iter().position(|k| k.0 == key) {
+            let i = i as u32;
+            return Some(GridKey::Cell(i / self.columns, i % self.columns));
+        }
+        match key {
+            k if k == self.warp.0 => Some(GridKey::Warp),
+            k if k == self.click.0 => Some(GridKey::Click),
+            k if k == self.cancel.0 => Some(GridKey::Cancel),
+            _ => None,
+        }
+    }
+}
+
+// keynav-style navigation: the region starts as the whole screen, every cell key narrows
+// it down to that cell of the grid laid over it, and the pointer follows its center.
+// Kept fractional so the cells stay even after a few rounds on an odd-sized screen.
+pub struct Grid {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+impl Grid {
+    pub fn new(screen: &ScreenConfig) -> Self {
+        Grid { x: 0.0, y: 0.0, width: screen.width as f32, height: screen.height as f32 }
+    }
+
+    pub fn select(&mut self, row: u32, column: u32, rows: u32, columns: u32) {
+        self.width /= columns as f32;
+        self.height /= rows as f32;
+        self.x += column as f32 * self.width;
+        self.y += row as f32 * self.height;
+    }
+
+    pub fn center(&self) -> Warp {
+        Warp::At((self.x + self.width / 2.0) as u32, (self.y + self.height / 2.0) as u32)
+    }
+}
@@ -0,0 +1,197 @@
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+
+use evdev::Key;
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::bindings::Warp;
+use crate::config::{HintsConfig, ScreenConfig};
+
+// One labeled cell of the hint lattice, in screen pixels.
+pub struct Hint {
+    pub label: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Hint {
+    pub fn center(&self) -> Warp {
+        Warp::At(self.x + self.width / 2, self.y + self.height / 2)
+    }
+}
+
+impl HintsConfig {
+    // Labels go row by row, two letters each: the first picks a block of as many cells
+    // as the alphabet has letters, the second the cell within it. The same config always
+    // gives the same layout, so it can be printed and learned.
+    pub fn label(&self, i: usize) -> String {
+        let letters: Vec<char> = self.alphabet.chars().collect();
+        let n = letters.len();
+        [letters[i / n], letters[i % n]].iter().collect()
+    }
+
+    pub fn layout(&self, screen: &ScreenConfig) -> Vec<Hint> {
+        // edges computed from the screen size, so the rounding doesn't add up. In u64,
+        // the product doesn't fit a u32 on big screens, the result never exceeds `size`.
+        let edge = |size: u32, i: u32, n: u32| (size as u64 * i as u64 / n as u64) as u32;
+        let mut hints = Vec::with_capacity(self.rows as usize * self.columns as usize);
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let x = edge(screen.width, column, self.columns);
+                let y = edge(screen.height, row, self.rows);
+                hints.push(Hint {
+                    label: self.label(hints.len()),
+                    x,
+                    y,
+                    width: edge(screen.width, column + 1, self.columns) - x,
+                    height: edge(screen.height, row + 1, self.rows) - y,
+                });
+            }
+        }
+        hints
+    }
+
+    // The alphabet letter a key types, if any.
+    pub fn letter(&self, key: Key) -> Option<char> {
+        let name = format!("{:?}", key);
+        let mut chars = name.strip_prefix("KEY_")?.chars();
+        let c = chars.next()?.to_ascii_lowercase();
+        (chars.next().is_none() && self.alphabet.contains(c)).then_some(c)
+    }
+}
+
+// kmouse hints --print
+pub fn print(config: &HintsConfig, screen: &ScreenConfig) {
+    println!(
+        "{}x{} hints over {}x{}, each {}x{} pixels",
+        config.rows,
+        config.columns,
+        screen.width,
+        screen.height,
+        screen.width / config.columns,
+        screen.height / config.rows
+    );
+    let hints = config.layout(screen);
+    for row in hints.chunks(config.columns as usize) {
+        let labels: Vec<&str> = row.iter().map(|h| h.label.as_str()).collect();
+        println!("{}", labels.join(" "));
+    }
+}
+
+// Feeds an external overlay that draws the labels, since KMouse can't draw anything
+// itself. The overlay connects to the unix socket and reads one JSON object per line:
+//
+//   {"event":"show","hints":[{"label":"aa","x":0,"y":0,"width":96,"height":90},...]}
+//   {"event":"typed","prefix":"a"}       the first letter, the overlay may filter
+//   {"event":"hide"}
+//
+// Writes never block the main loop, a client that can't keep up gets dropped.
+pub struct HintServer {
+    listener: Option<(UnixListener, PathBuf)>,
+    clients: Vec<UnixStream>,
+}
+
+impl HintServer {
+    pub fn new(path: Option<&Path>) -> std::io::Result<Self> {
+        let listener = match path {
+            Some(path) => {
+                // left over from a previous run that didn't get to clean up, but only a
+                // socket, a typo in the config mustn't delete some other file
+                match std::fs::symlink_metadata(path) {
+                    Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+                    Ok(_) => {
+                        let message = format!("{} exists and is not a socket", path.display());
+                        return Err(std::io::Error::new(ErrorKind::AlreadyExists, message));
+                    }
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+                Some((UnixListener::bind(path)?, path.to_path_buf()))
+            }
+            None => None,
+        };
+        Ok(HintServer { listener, clients: vec![] })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.listener.as_ref().map(|(_, path)| path.as_path())
+    }
+
+    // Resolves with the next overlay connecting, never without a socket.
+    pub async fn accept(&self) -> std::io::Result<UnixStream> {
+        match &self.listener {
+            Some((listener, _)) => listener.accept().await.map(|(stream, _)| stream),
+            None => std::future::pending().await,
+        }
+    }
+
+    pub fn add(&mut self, client: UnixStream) {
+        self.clients.push(client);
+    }
+
+    pub fn show(&mut self, hints: &[Hint]) {
+        let hints: Vec<String> = hints
+            .iter()
+            .map(|h| {
+                format!(
+                    r#"{{"label":"{}","x":{},"y":{},"width":{},"height":{}}}"#,
+                    h.label, h.x, h.y, h.width, h.height
+                )
+            })
+            .collect();
+        self.send(&format!(r#"{{"event":"show","hints":[{}]}}"#, hints.join(",")));
+    }
+
+    pub fn typed(&mut self, prefix: char) {
+        self.send(&format!(r#"{{"event":"typed","prefix":"{}"}}"#, prefix));
+    }
+
+    pub fn hide(&mut self) {
+        self.send(r#"{"event":"hide"}"#);
+    }
+
+    fn send(&mut self, message: &str) {
+        let line = format!("{}\n", message);
+        self.clients.retain(|client| matches!(client.try_write(line.as_bytes()), Ok(n) if n == line.len()));
+    }
+}
+
+impl Drop for HintServer {
+    fn drop(&mut self) {
+        if let Some(path) = self.path() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("kmouse-test-{}-{}", std::process::id(), name))
+    }
+
+    #[tokio::test]
+    async fn socket_path_that_is_a_file_is_left_alone() {
+        let path = temp_path("file");
+        std::fs::write(&path, "keep").unwrap();
+        assert!(HintServer::new(Some(&path)).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stale_socket_is_replaced() {
+        let path = temp_path("socket");
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let server = HintServer::new(Some(&path)).unwrap();
+        assert_eq!(server.path(), Some(path.as_path()));
+        drop(server);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use evdev::Key;
+
+use crate::bindings::{Action, Bindings};
+
+// Which keys are down on which source, and how many of them hold each action. With two
+// keyboards bound the same way, F15 held on both and released on one keeps moving up:
+// an action starts with the first key that holds it and stops with the last one.
+//
+// A key remembers the action it started, so its release ends that one even when the
+// bindings changed in between.
+#[derive(Default)]
+pub struct KeyState {
+    down: HashMap<(usize, Key), Action>,
+    holders: HashMap<Action, u32>,
+}
+
+impl KeyState {
+    // The action to run for a key event from `source`, None when there is nothing to do:
+    // an unbound key, an action some other key already holds, a release of a key we
+    // never saw go down, autorepeat.
+    pub fn update(&mut self, source: usize, key: Key, value: i32, bindings: &Bindings) -> Option<Action> {
+        match value {
+            1 => {
+                let action = bindings.get(key)?;
+                if self.down.insert((source, key), action).is_some() {
+                    return None;
+                }
+                let holders = self.holders.entry(action).or_insert(0);
+                *holders += 1;
+                (*holders == 1).then_some(action)
+            }
+            0 => self.release(source, key),
+            _ => None,
+        }
+    }
+
+    fn release(&mut self, source: usize, key: Key) -> Option<Action> {
+        let action = self.down.remove(&(source, key))?;
+        let holders = self.holders.get_mut(&action)?;
+        *holders -= 1;
+        if *holders > 0 {
+            return None;
+        }
+        self.holders.remove(&action);
+        Some(action)
+    }
+
+    // A source went away with keys still down, ends the actions only they held.
+    pub fn release_source(&mut self, source: usize) -> Vec<Action> {
+        let keys: Vec<(usize, Key)> = self.down.keys().filter(|(s, _)| *s == source).copied().collect();
+        keys.into_iter().filter_map(|(source, key)| self.release(source, key)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bindings::Direction;
+
+    const UP: Action = Action::Move(Direction::Up);
+    const DOWN: Action = Action::Move(Direction::Down);
+
+    #[test]
+    fn two_sources_share_an_action() {
+        let (mut state, bindings) = (KeyState::default(), Bindings::default());
+        assert_eq!(state.update(0, Key::KEY_F15, 1, &bindings), Some(UP));
+        assert_eq!(state.update(1, Key::KEY_F15, 1, &bindings), None);
+        // still held on the other keyboard
+        assert_eq!(state.update(0, Key::KEY_F15, 0, &bindings), None);
+        assert_eq!(state.update(1, Key::KEY_F15, 0, &bindings), Some(UP));
+    }
+
+    #[test]
+    fn release_on_another_source_is_ignored() {
+        let (mut state, bindings) = (KeyState::default(), Bindings::default());
+        assert_eq!(state.update(0, Key::KEY_F15, 1, &bindings), Some(UP));
+        assert_eq!(state.update(1, Key::KEY_F15, 0, &bindings), None);
+        assert_eq!(state.update(0, Key::KEY_F15, 0, &bindings), Some(UP));
+        assert_eq!(state.update(0, Key::KEY_F15, 0, &bindings), None);
+    }
+
+    #[test]
+    fn autorepeat_does_nothing() {
+        let (mut state, bindings) = (KeyState::default(), Bindings::default());
+        assert_eq!(state.update(0, Key::KEY_F15, 1, &bindings), Some(UP));
+        assert_eq!(state.update(0, Key::KEY_F15, 2, &bindings), None);
+        // a press we already saw, e.g. a repeat sent as 1, doesn't count twice
+        assert_eq!(state.update(0, Key::KEY_F15, 1, &bindings), None);
+        assert_eq!(state.update(0, Key::KEY_F15, 0, &bindings), Some(UP));
+    }
+
+    #[test]
+    fn release_ends_the_action_the_key_started() {
+        let mut state = KeyState::default();
+        let before = Bindings::default();
+        let after: Bindings = toml::from_str(r#"KEY_F15 = "move:down""#).unwrap();
+        assert_eq!(state.update(0, Key::KEY_F15, 1, &before), Some(UP));
+        assert_eq!(state.update(0, Key::KEY_F15, 0, &after), Some(UP));
+        assert_eq!(state.update(0, Key::KEY_F15, 1, &after), Some(DOWN));
+    }
+
+    #[test]
+    fn release_source_ends_what_only_it_held() {
+        let (mut state, bindings) = (KeyState::default(), Bindings::default());
+        assert_eq!(state.update(0, Key::KEY_F15, 1, &bindings), Some(UP));
+        assert_eq!(state.update(0, Key::KEY_F14, 1, &bindings), Some(DOWN));
+        assert_eq!(state.update(1, Key::KEY_F14, 1, &bindings), None);
+        assert_eq!(state.release_source(0), vec![UP]);
+        assert_eq!(state.release_source(0), vec![]);
+        assert_eq!(state.update(1, Key::KEY_F14, 0, &bindings), Some(DOWN));
+    }
+}
@@ -1,400 +1,569 @@
-use std::time::{Duration, SystemTime};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use evdev::{uinput::VirtualDeviceBuilder, AttributeSet, Key, RelativeAxisType, InputEvent, EventType, InputEventKind, EventStream};
-use tokio::time;
-
-// Acceleration Mode
-// https://en.wikipedia.org/wiki/Mouse_keys#MouseKeysAccel
-static MK_ACTION_DELTA: i32 = 7;
-static MK_DELAY: u64 = 32; 	//milliseconds between the initial key press and first repeated motion event
-static MK_INTERVAL: u64 = 8; // 	milliseconds between repeated motion events
-static MK_MAX_SPEED: i32 = 10; 	// steady speed (in action_delta units) applied each event
-static MK_TIME_TO_MAX: u64 = 120; 	// number of events (count) accelerating to steady speed
-static MK_CURVE: i32 = 500; //	ramp used to reach maximum pointer speed
-
-static MK_WHEEL_DELAY: u64 = 10; 	//milliseconds between the initial key press and first repeated motion event
-static MK_WHEEL_DELTA: i32 = 3; // hmm.
-static MK_WHEEL_INTERVAL: u64 = 80; // hmm.
-static MK_WHEEL_MAX_SPEED: u64 = 8;
-static MK_WHEEL_TIME_TO_MAX: u64 = 80;
-// Kinetic mode
-// https://docs.qmk.fm/#/feature_mouse_keys?id=kinetic-mode
-/*static MOUSEKEY_DELAY: u64 = 5; //  	Delay between pressing a movement key and cursor movement
-static MOUSEKEY_INTERVAL: u64 =  10; // 	Time between cursor movements in milliseconds
-static MOUSEKEY_MOVE_DELTA: f32 = 16.0; //	Step size for accelerating from initial to base speed
-static MOUSEKEY_INITIAL_SPEED: f32 = 100.0; // 	Initial speed of the cursor in pixel per second
-static MOUSEKEY_BASE_SPEED: f32 =  	5000.0; // 	Maximum cursor speed at which acceleration stops
-*/
 
+use evdev::{uinput::VirtualDeviceBuilder, AttributeSet, Key, RelativeAxisType, InputEvent, EventType, InputEventKind};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time;
 
+mod accel;
+mod bindings;
+mod cli;
+mod config;
+mod devices;
+mod drag;
+mod grid;
+mod keys;
+mod hints;
+mod marks;
+mod motion;
+mod output;
+mod reload;
+mod scheduler;
+mod sources;
+
+use bindings::{Action, Button, Warp};
+use cli::{Cli, Command, RunArgs};
+use config::{Config, OnTurn, Opposite};
+use drag::DragLocks;
+use grid::{Grid, GridKey};
+use hints::HintServer;
+use keys::KeyState;
+use marks::Marks;
+use motion::Motion;
+use output::Output;
+use scheduler::Scheduler;
+use sources::{SourceEvent, Sources};
+
+// What woke the main loop up
+enum Wake {
+    Input(usize, InputEvent),
+    Released(Vec<Action>), // the actions only a lost device held
+    Tick,
+}
 
+// Modes that take over the keys until they're done, see grid.rs and hints.rs
+enum Mode {
+    Grid(Grid),
+    Hints(Option<char>), // the first letter of a label, once typed
+    MarkSet,
+    MarkJump,
+}
 
-pub fn pick_device() -> evdev::Device {
-    use std::io::prelude::*;
+fn load_config(path: Option<&Path>) -> (Config, Option<PathBuf>) {
+    match config::load(path) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-    let mut args = std::env::args_os();
-    args.next();
-    if let Some(dev_file) = args.next() {
-        evdev::Device::open(dev_file).unwrap()
+// kmouse curve: what the acceleration profile makes of a key held for `until`
+fn print_curve(config: &Config, wheel: bool, until: u64, step: Option<u64>) {
+    let (profile, interval) = if wheel {
+        (config.wheel.profile(), config.wheel.interval)
     } else {
-        let mut devices = evdev::enumerate().map(|t| t.1).collect::<Vec<_>>();
-        let maybe_kmonad = devices.iter().enumerate().find(|d| d.1.name() == Some("KMonad output"));
-        if let Some((n, _)) = maybe_kmonad {
-            devices.into_iter().nth(n).unwrap()
-
-        } else {
-        // readdir returns them in reverse order from their eventN names for some reason
-        devices.reverse();
-        for (i, d) in devices.iter().enumerate() {
-            println!("{}: {}", i, d.name().unwrap_or("Unnamed device"));
-        }
-        print!("Select the device [0-{}]: ", devices.len());
-        let _ = std::io::stdout().flush();
-        let mut chosen = String::new();
-        std::io::stdin().read_line(&mut chosen).unwrap();
-        let n = chosen.trim().parse::<usize>().unwrap();
-        devices.into_iter().nth(n).unwrap()
-        }
+        (config.pointer.profile(), config.pointer.interval())
+    };
+    let step = Duration::from_millis(step.unwrap_or(interval).max(1));
+    let unit = if wheel { "notches" } else { "pixels" };
+    println!("{:>8} {:>12} {:>12}", "ms", format!("{}/s", unit), unit);
+    let mut held = Duration::ZERO;
+    let mut distance = 0.0;
+    while held <= Duration::from_millis(until) {
+        println!("{:>8} {:>12.1} {:>12.1}", held.as_millis(), profile.speed(held), distance);
+        held += step;
+        distance += accel::distance(profile.as_ref(), held, step);
     }
 }
-// 
+
+//
 // libinput debug-events
 //
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let d = pick_device();
-    println!("{:?}", d.input_id());
+async fn main() {
+    let cli = Cli::parse_checked();
+    if cli.list_devices {
+        devices::list(cli.verbose);
+        return;
+    }
+    let run_args = match cli.command {
+        None => cli.run,
+        Some(Command::Run(args)) => args,
+        Some(Command::List) => {
+            devices::list(cli.verbose);
+            return;
+        }
+        Some(Command::CheckConfig) => {
+            match load_config(cli.config.as_deref()) {
+                (_, Some(path)) => println!("{}: ok", path.display()),
+                (_, None) => println!("No config file found, the defaults are used"),
+            }
+            return;
+        }
+        Some(Command::Curve { wheel, until, step }) => {
+            print_curve(&load_config(cli.config.as_deref()).0, wheel, until, step);
+            return;
+        }
+        Some(Command::Hints { .. }) => {
+            let (config, _) = load_config(cli.config.as_deref());
+            match &config.screen {
+                Some(screen) => hints::print(&config.hints, screen),
+                None => {
+                    eprintln!("Hints need a [screen] with width and height in the config");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+    };
+    if let Err(e) = run(run_args, cli.config, cli.verbose).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(args: RunArgs, config_path: Option<PathBuf>, verbose: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut config, config_path) = load_config(config_path.as_deref());
+    if let Some(path) = &config_path {
+        println!("Loaded config from {}", path.display());
+    }
+    // the command line replaces the config's rule as a whole, they don't mix
+    let mut rule = args.device_rule().unwrap_or_else(|| config.device.clone());
+    if rule.is_empty() {
+        rule.name = Some("KMonad output".to_string());
+    }
+    let mut sources = Sources::new(rule.clone(), &args.virtual_name);
+    if sources.is_empty() {
+        // not an error, it gets picked up once it shows up, e.g. KMouse started before KMonad
+        println!("No device matches {} yet, waiting for one\n{}", devices::describe_rule(&rule), devices::candidates());
+    }
 
     let mut keys = AttributeSet::<Key>::new();
-    keys.insert(Key::BTN_LEFT);
-    keys.insert(Key::BTN_RIGHT);
+    for button in Button::ALL {
+        keys.insert(button.key());
+    }
 
     let mut axes = AttributeSet::<RelativeAxisType>::new();
     axes.insert(RelativeAxisType::REL_X);
     axes.insert(RelativeAxisType::REL_Y);
     axes.insert(RelativeAxisType::REL_WHEEL);
-    // it's working! but not in sway yet.., which means I need to send also REL_WHEEL every
-    // 120 notches
-    //axes.insert(RelativeAxisType::REL_WHEEL_HI_RES); //= WHEEL * 120
-    /*
-    let abs_x = UinputAbsSetup::new(
-        AbsoluteAxisType::ABS_X,
-        AbsInfo::new(256, 0, 512, 20, 20, 1)
-    );
-    let abs_y = UinputAbsSetup::new(
-        AbsoluteAxisType::ABS_X,
-        AbsInfo::new(256, 0, 512, 20, 20, 1)
-    );
-    */
-
-
-    let mut device = VirtualDeviceBuilder::new()?
-        .name("KMouse")
-        .with_relative_axes(&axes)?
-       // .with_absolute_axis(&abs_x)?
-       // .with_absolute_axis(&abs_y)?
-        .with_keys(&keys)?
+    // sway and other notch-only clients ignore the hi-res axis, see accel::HiResWheel
+    axes.insert(RelativeAxisType::REL_WHEEL_HI_RES); //= WHEEL * 120
+    axes.insert(RelativeAxisType::REL_HWHEEL);
+    axes.insert(RelativeAxisType::REL_HWHEEL_HI_RES);
+
+
+    let uinput = |e: std::io::Error| {
+        format!("Creating the virtual device `{}` failed, is /dev/uinput writable? {}", args.virtual_name, e)
+    };
+    let mut device = VirtualDeviceBuilder::new()
+        .map_err(uinput)?
+        .name(&args.virtual_name)
+        .with_relative_axes(&axes)
+        .map_err(uinput)?
+        .with_keys(&keys)
+        .map_err(uinput)?
         .build()
-        .unwrap();
+        .map_err(uinput)?;
 
-    for path in device.enumerate_dev_nodes_blocking()? {
-        let path = path?;
-        println!("Available as {}", path.display());
+    // only informational, the device works without knowing its node
+    match device.enumerate_dev_nodes_blocking() {
+        Ok(paths) => {
+            for path in paths.flatten() {
+                println!("Available as {}", path.display());
+            }
+        }
+        Err(e) => eprintln!("Looking up the virtual device's node failed: {}", e),
     }
-    let mut movement_start_time: SystemTime = SystemTime::UNIX_EPOCH;
-    let mut wheel_start_time: SystemTime = SystemTime::UNIX_EPOCH;
-
-    let mut num_repeat: u64 = 0;
-    let mut wheel_num_repeat:  u64 = 0;
-
-    let mut right_pressed = false;
-    let mut left_pressed = false;
-    let mut up_pressed = false;
-    let mut down_pressed = false;
-
-    let mut wheel_up_pressed = false;
-    let mut wheel_down_pressed = false;
-
-    let mut left_button_down = false;
-
-    let mut interval = time::interval(Duration::from_millis(MK_INTERVAL));
+    let mut device = Output::new(device, &args.virtual_name);
+    // absolute axes for warping live on a second device, see output.rs
+    device
+        .set_screen(config.screen.as_ref())
+        .map_err(|e| format!("Creating the absolute device `{} absolute` failed: {}", args.virtual_name, e))?;
+    let mut pointer = Motion::new(config.pointer.opposite, config.pointer.on_turn);
+    let mut wheel = Motion::new(Opposite::Cancel, OnTurn::Keep);
+    let mut x_remainder = accel::Remainder::default();
+    let mut y_remainder = accel::Remainder::default();
+    let mut wheel_remainder = accel::HiResWheel::default();
+    let mut hwheel_remainder = accel::HiResWheel::default();
+
+    let mut scheduler = Scheduler::default();
+    let mut drag = DragLocks::default();
+    let mut mode: Option<Mode> = None;
+    let mut hint_server = HintServer::new(config.hints.socket.as_deref()).map_err(|e| {
+        let path = config.hints.socket.as_deref().unwrap_or(Path::new("")).display();
+        format!("Opening the hint socket {} failed: {}", path, e)
+    })?;
+    let mut marks = Marks::load(config.marks.file.clone().or_else(marks::state_file));
+    // the source gets grabbed at the first key release after a mode starts, normally the
+    // key that started it, grabbing right away would leave that one stuck down for
+    // everybody else
+    let mut grab_pending = false;
+    let mut grabbed = false;
+
+    let mut interval = time::interval(Duration::from_millis(config.pointer.interval()));
     interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
-    let mut wheel_interval = time::interval(Duration::from_millis(MK_WHEEL_INTERVAL));
+    let mut wheel_interval = time::interval(Duration::from_millis(config.wheel.interval));
     wheel_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
-    let mut events = d.into_event_stream()?;
+    let mut pointer_accel = config.pointer.profile();
+    let mut wheel_accel = config.wheel.profile();
+    let mut watcher = reload::ConfigWatcher::new(config_path.as_deref())?;
+    let mut key_state = KeyState::default();
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
     loop {
-        let maybe_event : Option<InputEvent> = tokio::select!  {
-            e = wait_for_input(&mut events) => Some(e?),
+        let wake = tokio::select!  {
+            e = sources.next() => match e {
+                SourceEvent::Input(source, ev) => Wake::Input(source, ev),
+                SourceEvent::Added(source, name) => {
+                    println!("Reading from [{}] {}", source, name);
+                    continue;
+                }
+                SourceEvent::Removed(source, name, e) => {
+                    println!("Lost [{}] {}: {}, waiting for it to come back", source, name, e);
+                    // whatever only its keys held would be stuck now
+                    Wake::Released(key_state.release_source(source))
+                }
+            },
             _ = async {
-                if right_pressed || left_pressed || up_pressed || down_pressed {
+                if pointer.is_active() {
                     interval.tick().await;
-                } else if wheel_up_pressed || wheel_down_pressed {
+                } else if wheel.is_active() {
                     wheel_interval.tick().await;
                 } else {
                     tokio::time::sleep(Duration::from_millis(1000)).await;
                 }
-            } => None
-            
-        };
-        if let Some(ev) = maybe_event {
-            // event
-            // mouse 17, 18, 19
-            //println!("{:?}", ev);
-        match ev.kind() {
-            InputEventKind::Key(Key::KEY_F17) if ev.value() == 1 => {
-              if !left_button_down {
-                let left_click = InputEvent::new(EventType::KEY, Key::BTN_LEFT.0, 1);
-                device.emit(&[left_click]).unwrap();
-                //left_button_down = false;
-                time::sleep(Duration::from_millis(32)).await;
-              }
-              let left_up = InputEvent::new(EventType::KEY, Key::BTN_LEFT.0, 0);
-              device.emit(&[left_up]).unwrap();
-              left_button_down = false;
-            },
-            InputEventKind::Key(Key::KEY_F18) if ev.value() == 1  => {
-              let left_click = InputEvent::new(EventType::KEY, Key::BTN_RIGHT.0, 1);
-              device.emit(&[left_click]).unwrap();
-              time::sleep(Duration::from_millis(32)).await;
-              let left_up = InputEvent::new(EventType::KEY, Key::BTN_RIGHT.0, 0);
-              device.emit(&[left_up]).unwrap();
-            },
-            InputEventKind::Key(Key::KEY_F19) if ev.value() == 1   => {
-              if left_button_down {
-                  let left_click = InputEvent::new(EventType::KEY, Key::BTN_LEFT.0, 0);
-                  device.emit(&[left_click]).unwrap();
-                  left_button_down = false;
-              } else {
-                  let left_click = InputEvent::new(EventType::KEY, Key::BTN_LEFT.0, 1);
-                  device.emit(&[left_click]).unwrap();
-                  left_button_down = true;
-
-              }
-            },
-            InputEventKind::Key(Key::KEY_F15)  => {
-                if ev.value() == 1 {
-                    up_pressed = true;
-                    if movement_start_time == SystemTime::UNIX_EPOCH {
-                      movement_start_time = ev.timestamp();
-                      num_repeat = 0;
+            } => Wake::Tick,
+            due = scheduler.due() => {
+                for events in due {
+                    device.emit(&events)?;
+                }
+                continue;
+            }
+            client = hint_server.accept() => {
+                match client {
+                    Ok(client) => hint_server.add(client),
+                    Err(e) => eprintln!("Accepting a hint overlay failed: {}", e),
+                }
+                continue;
+            }
+            // leaving the loop drops the device, which lets go of every button
+            _ = interrupt.recv() => break,
+            _ = terminate.recv() => break,
+            _ = drag.idle(config.drag.timeout()) => {
+                if let Some(timeout) = config.drag.timeout() {
+                    for button in drag.unlock_idle(timeout) {
+                        scheduler.cancel(button.key().0);
+                        device.button(button, 0)?;
                     }
-
-                } else {
-                    up_pressed = false;
-                    if !(up_pressed || down_pressed || left_pressed || right_pressed) {
-                        movement_start_time = SystemTime::UNIX_EPOCH;
+                }
+                continue;
+            }
+            _ = watcher.changed() => {
+                match config::load(config_path.as_deref()) {
+                    Ok((new_config, path)) => {
+                        if new_config.pointer.interval() != config.pointer.interval() {
+                            interval = time::interval(Duration::from_millis(new_config.pointer.interval()));
+                            interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+                        }
+                        if new_config.wheel.interval != config.wheel.interval {
+                            wheel_interval = time::interval(Duration::from_millis(new_config.wheel.interval));
+                            wheel_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+                        }
+                        if let Err(e) = device.set_screen(new_config.screen.as_ref()) {
+                            eprintln!("Creating the absolute device failed, warping won't work: {}", e);
+                        }
+                        // Sources keeps the devices it has open, telling which ones to let
+                        // go of isn't worth it for something that changes this rarely
+                        if new_config.device != config.device && args.device_rule().is_none() {
+                            eprintln!("The [device] rule changed, restart KMouse for it to apply");
+                        }
+                        if new_config.hints.socket.as_deref() != hint_server.path() {
+                            match HintServer::new(new_config.hints.socket.as_deref()) {
+                                Ok(server) => hint_server = server,
+                                Err(e) => eprintln!("Opening the hint socket failed: {}", e),
+                            }
+                        }
+                        if new_config.marks.file != config.marks.file {
+                            marks = Marks::load(new_config.marks.file.clone().or_else(marks::state_file));
+                        }
+                        config = new_config;
+                        pointer_accel = config.pointer.profile();
+                        wheel_accel = config.wheel.profile();
+                        pointer.opposite = config.pointer.opposite;
+                        pointer.on_turn = config.pointer.on_turn;
+                        // keys held across the reload still end what they started, KeyState
+                        // remembers the action of each one
+                        match path {
+                            Some(path) => println!("Reloaded config from {}", path.display()),
+                            None => println!("No config file found, using the defaults"),
+                        }
                     }
+                    Err(e) => eprintln!("{}, keeping the old config", e),
                 }
-            },
-            InputEventKind::Key(Key::KEY_F16)  => {
-                if ev.value() == 1 {
-                    right_pressed = true;
-                    if movement_start_time == SystemTime::UNIX_EPOCH {
-                      movement_start_time = ev.timestamp();
-                      num_repeat = 0;
+                continue;
+            }
+        };
+        let mut actions: Vec<(Action, i32)> = vec![];
+        match wake {
+            Wake::Released(released) => actions.extend(released.into_iter().map(|action| (action, 0))),
+            Wake::Input(source, ev) => {
+                // event
+                // mouse 17, 18, 19
+                if verbose {
+                    println!("[{}] {:?}", source, ev);
+                }
+                if let (Some(m), InputEventKind::Key(key)) = (&mut mode, ev.kind()) {
+                    // presses belong to the mode, releases still go to the bindings below so a
+                    // key held from before doesn't get stuck
+                    if ev.value() == 0 && grab_pending {
+                        grab_pending = false;
+                        match sources.grab() {
+                            Ok(()) => grabbed = true,
+                            Err(e) => eprintln!("Grabbing the source devices failed: {}", e),
+                        }
+                    } else if ev.value() != 0 {
+                        let mut done = false;
+                        let mut click = false;
+                        match m {
+                            Mode::Grid(g) => match config.grid.key(key) {
+                                Some(GridKey::Cell(row, column)) if ev.value() == 1 => {
+                                    g.select(row, column, config.grid.rows, config.grid.columns);
+                                    device.warp(g.center())?;
+                                    drag.touch();
+                                }
+                                Some(GridKey::Click) => (done, click) = (true, true),
+                                Some(GridKey::Warp | GridKey::Cancel) => done = true,
+                                _ => {}
+                            },
+                            Mode::Hints(first) => match config.hints.letter(key) {
+                                _ if key == config.hints.click.0 => (done, click) = (true, true),
+                                _ if key == config.hints.cancel.0 => done = true,
+                                Some(c) if ev.value() == 1 => match first.take() {
+                                    None => {
+                                        *first = Some(c);
+                                        hint_server.typed(c);
+                                    }
+                                    Some(f) => {
+                                        // a label that doesn't exist starts over
+                                        let label: String = [f, c].iter().collect();
+                                        let screen = config.screen.as_ref();
+                                        let layout = screen.map(|s| config.hints.layout(s)).unwrap_or_default();
+                                        if let Some(hint) = layout.iter().find(|h| h.label == label) {
+                                            device.warp(hint.center())?;
+                                            drag.touch();
+                                        }
+                                        // all labels again for the next one
+                                        hint_server.show(&layout);
+                                    }
+                                },
+                                _ => {}
+                            },
+                            Mode::MarkSet if ev.value() == 1 => {
+                                done = true;
+                                if key != config.marks.cancel.0 {
+                                    match device.position() {
+                                        Some(position) => {
+                                            if let Err(e) = marks.set(key, position) {
+                                                eprintln!("Saving the marks failed: {}", e);
+                                            }
+                                        }
+                                        None => {
+                                            eprintln!("The pointer position is unknown until it gets warped somewhere")
+                                        }
+                                    }
+                                }
+                            }
+                            Mode::MarkJump if ev.value() == 1 => {
+                                done = true;
+                                if let Some((x, y)) = marks.get(key).filter(|_| key != config.marks.cancel.0) {
+                                    device.warp(Warp::At(x, y))?;
+                                    drag.touch();
+                                }
+                            }
+                            Mode::MarkSet | Mode::MarkJump => {}
+                        }
+                        if click {
+                            scheduler.clicks(Button::Left.key(), 1, config.click.press(), config.click.gap());
+                        }
+                        if done {
+                            if let Some(Mode::Hints(_)) = mode {
+                                hint_server.hide();
+                            }
+                            mode = None;
+                            grab_pending = false;
+                            if grabbed {
+                                grabbed = false;
+                                if let Err(e) = sources.ungrab() {
+                                    eprintln!("Releasing the source devices failed: {}", e);
+                                }
+                            }
+                        }
+                        continue;
                     }
-
-                } else {
-                    right_pressed = false;
-                    if !(up_pressed || down_pressed || left_pressed || right_pressed) {
-                        movement_start_time = SystemTime::UNIX_EPOCH;
+                }
+                if let InputEventKind::Key(key) = ev.kind() {
+                    if let Some(action) = key_state.update(source, key, ev.value(), &config.bindings) {
+                        actions.push((action, ev.value()));
                     }
                 }
-
-            },
-            InputEventKind::Key(Key::KEY_F14)  => {
-                if ev.value() == 1 {
-                    down_pressed = true;
-                    if movement_start_time == SystemTime::UNIX_EPOCH {
-                      movement_start_time = ev.timestamp();
-                      num_repeat = 0;
+            }
+            Wake::Tick => {
+                let mut events: Vec<InputEvent> = vec![];
+                let repeat_delay = Duration::from_millis(config.pointer.delay());
+                let wheel_repeat_delay = Duration::from_millis(config.wheel.delay);
+
+                if let Some((dx, dy)) = pointer.tick(pointer_accel.as_ref(), repeat_delay) {
+                    let (x, y) = (x_remainder.take(dx), y_remainder.take(dy));
+                    if x != 0 {
+                        events.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_X.0, x));
                     }
-
-                } else {
-                    down_pressed = false;
-                    if !(up_pressed || down_pressed || left_pressed || right_pressed) {
-                        movement_start_time = SystemTime::UNIX_EPOCH;
+                    if y != 0 {
+                        events.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_Y.0, y));
                     }
                 }
-
-            },
-            InputEventKind::Key(Key::KEY_F13)  => {
-                if ev.value() == 1 {
-                    left_pressed = true;
-                    if movement_start_time == SystemTime::UNIX_EPOCH {
-                        movement_start_time = ev.timestamp();
-                        num_repeat = 0;
+                if let Some((dx, dy)) = wheel.tick(wheel_accel.as_ref(), wheel_repeat_delay) {
+                    // REL_WHEEL is positive upwards, REL_HWHEEL to the right
+                    let (hi_res, notches) = wheel_remainder.take(-dy);
+                    let (h_hi_res, h_notches) = hwheel_remainder.take(dx);
+                    if notches != 0 {
+                        events.push(InputEvent::new(
+                            EventType::RELATIVE,
+                            RelativeAxisType::REL_WHEEL.0,
+                            notches,
+                        ));
                     }
-
-                } else {
-                    left_pressed = false;
-                    if !(up_pressed || down_pressed || left_pressed || right_pressed) {
-                        movement_start_time = SystemTime::UNIX_EPOCH;
+                    if hi_res != 0 {
+                        events.push(InputEvent::new(
+                            EventType::RELATIVE,
+                            RelativeAxisType::REL_WHEEL_HI_RES.0,
+                            hi_res,
+                        ));
+                    }
+                    if h_notches != 0 {
+                        events.push(InputEvent::new(
+                            EventType::RELATIVE,
+                            RelativeAxisType::REL_HWHEEL.0,
+                            h_notches,
+                        ));
+                    }
+                    if h_hi_res != 0 {
+                        events.push(InputEvent::new(
+                            EventType::RELATIVE,
+                            RelativeAxisType::REL_HWHEEL_HI_RES.0,
+                            h_hi_res,
+                        ));
                     }
                 }
 
-            },
-            InputEventKind::Key(Key::KEY_F20)  => {
-                if ev.value() == 1 {
-                    wheel_up_pressed = true;
-                    wheel_start_time = ev.timestamp();
-                    wheel_num_repeat = 0;
-
-                } else {
-                    wheel_up_pressed = false;
-                }
-
-            },
-            InputEventKind::Key(Key::KEY_F21)  => {
-                if ev.value() == 1 {
-                    wheel_down_pressed = true;
-                    wheel_start_time = ev.timestamp();
-                    wheel_num_repeat = 0;
-
-                } else {
-                    wheel_down_pressed = false;
+                if !events.is_empty() {
+                    drag.touch();
+                    device.emit(&events)?;
                 }
-
-            },
-            _ => {}
+                // timer tick
+            }
         }
-        //println!("{:?}", ev);
-        } else {
-            let mut events: Vec<InputEvent> = vec![];
-            let repeat_delay = time::Duration::from_millis(MK_DELAY);
-            let wheel_repeat_delay = time::Duration::from_millis(MK_WHEEL_DELAY);
-            
-            // TODO: emit side moves
-            if right_pressed && (num_repeat == 0 || movement_start_time.elapsed().unwrap() > repeat_delay) {
-                //let d = curve(movement_start_time);
-                let mut d = mouse_keys_accel(num_repeat);
-                if up_pressed || down_pressed {
-                    d = d * 181 / 256;
-                    if d == 0 {
-                        d = 1;
+        for (action, value) in actions {
+            if value == 1 {
+                drag.touch();
+            }
+            // 1 is press, 0 is release, 2 is autorepeat which we don't care about
+            match action {
+                Action::Click(button, count) if value == 1 => {
+                    // clicking a drag locked button just lets go of it
+                    if drag.unlock(button) {
+                        scheduler.cancel(button.key().0);
+                        device.button(button, 0)?;
+                    } else {
+                        if config.drag.release_on_click {
+                            for locked in drag.unlock_all() {
+                                scheduler.cancel(locked.key().0);
+                                device.button(locked, 0)?;
+                            }
+                        }
+                        scheduler.clicks(button.key(), count, config.click.press(), config.click.gap());
                     }
                 }
-                //let d = kinetic_action(movement_start_time);
-                let move_right = InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_X.0, d);
-                events.push(move_right);
-            }
-            if left_pressed  && (num_repeat == 0 || movement_start_time.elapsed().unwrap() > repeat_delay) {
-                // let d = curve(movement_start_time);
-                let mut d = mouse_keys_accel(num_repeat);
-                if up_pressed || down_pressed {
-                    d = d * 181 / 256;
-                    if d == 0 {
-                        d = 1;
+                Action::Press(button) if value == 1 => {
+                    // a release still queued from a click would let go of it right away
+                    scheduler.cancel(button.key().0);
+                    device.button(button, 1)?;
+                }
+                Action::Release(button) if value == 1 => {
+                    scheduler.cancel(button.key().0);
+                    device.button(button, 0)?;
+                    drag.unlock(button);
+                }
+                Action::Hold(button) if value != 2 => {
+                    scheduler.cancel(button.key().0);
+                    device.button(button, value)?;
+                    if value == 0 {
+                        drag.unlock(button);
                     }
                 }
-                //let d = kinetic_action(movement_start_time);
-                let move_left = InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_X.0, -d);
-                events.push(move_left);
-            }
-            if up_pressed  && (num_repeat == 0 || movement_start_time.elapsed().unwrap() > repeat_delay) {
-                //let d = curve(movement_start_time);
-                let mut d = mouse_keys_accel(num_repeat);
-                if left_pressed || right_pressed {
-                    d = d * 181 / 256;
-                    if d == 0 {
-                        d = 1;
+                Action::DragLock(button) if value == 1 => {
+                    if drag.unlock(button) {
+                        device.button(button, 0)?;
+                    } else {
+                        scheduler.cancel(button.key().0);
+                        device.button(button, 1)?;
+                        drag.lock(button);
                     }
                 }
-                //let d = kinetic_action(movement_start_time);
-                let move_up = InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_Y.0, -d);
-                events.push(move_up);
-            }
-            if down_pressed  && (num_repeat == 0 || movement_start_time.elapsed().unwrap() > repeat_delay) {
-              //let d = curve(movement_start_time);
-              let mut d = mouse_keys_accel(num_repeat);
-                if left_pressed || right_pressed {
-                    d = d * 181 / 256;
-                    if d == 0 {
-                        d = 1;
+                Action::Move(direction) if value != 2 => {
+                    let was_active = pointer.is_active();
+                    pointer.set(direction, value == 1);
+                    if !was_active {
+                        x_remainder.reset();
+                        y_remainder.reset();
                     }
                 }
-              //let d = kinetic_action(movement_start_time);
-              let move_down = InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_Y.0, d);
-              events.push(move_down);
-            }
-
-            if wheel_down_pressed  && (wheel_num_repeat == 0 || wheel_start_time.elapsed().unwrap() > wheel_repeat_delay) {
-              let d = wheel_keys_accel(wheel_num_repeat);
-              let wheel_down = InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_WHEEL.0, -d);
-              //let wheel_down = InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_WHEEL_HI_RES.0, -d * 120);
-              events.push(wheel_down);
-            }
-            if wheel_up_pressed  && (wheel_num_repeat == 0 || wheel_start_time.elapsed().unwrap() > wheel_repeat_delay) {
-              let d = wheel_keys_accel(wheel_num_repeat);
-              let wheel_up = InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_WHEEL.0, d);
-              //let wheel_up = InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_WHEEL_HI_RES.0, d * 120);
-              events.push(wheel_up);
-            }
-
-            if !events.is_empty() {
-                num_repeat += 1;
-                wheel_num_repeat += 1;
-                device.emit(&events).unwrap();
+                Action::Scroll(direction) if value != 2 => {
+                    let was_active = wheel.is_active();
+                    wheel.set(direction, value == 1);
+                    if !was_active {
+                        wheel_remainder.reset();
+                        hwheel_remainder.reset();
+                    }
+                }
+                Action::Warp(warp) if value == 1 => {
+                    device.warp(warp)?;
+                }
+                Action::Grid if value == 1 => {
+                    if let Some(screen) = &config.screen {
+                        let g = Grid::new(screen);
+                        device.warp(g.center())?;
+                        mode = Some(Mode::Grid(g));
+                        grab_pending = config.grid.grab;
+                        pointer.clear();
+                        wheel.clear();
+                    }
+                }
+                action @ (Action::MarkSet | Action::MarkJump) if value == 1 => {
+                    mode = Some(if action == Action::MarkSet {
+                        Mode::MarkSet
+                    } else {
+                        Mode::MarkJump
+                    });
+                    grab_pending = config.marks.grab;
+                }
+                Action::Hints if value == 1 => {
+                    if let Some(screen) = &config.screen {
+                        hint_server.show(&config.hints.layout(screen));
+                        mode = Some(Mode::Hints(None));
+                        grab_pending = config.hints.grab;
+                        pointer.clear();
+                        wheel.clear();
+                    }
+                }
+                _ => {}
             }
-            // timer tick
         }
     }
+    println!("Shutting down");
+    Ok(())
 }
 
-
-async fn wait_for_input(events: &mut EventStream) -> Result<InputEvent, Box<dyn std::error::Error>>  {
-    Ok(events.next_event().await?)
-}
-
-fn mouse_keys_accel(i: u64) -> i32 {
-  let r = if i == 0 {
-     1
-  } else if i >= MK_TIME_TO_MAX {
-      MK_MAX_SPEED * MK_ACTION_DELTA 
-  } else {
-      let action = MK_ACTION_DELTA as f32 * 
-          MK_MAX_SPEED as f32 * ((i as f32 / MK_TIME_TO_MAX as f32).powf((1000.0 + MK_CURVE as f32) / 1000.0));
-      action.floor() as i32
-  };
-  if r <= 0 { 1 } else { r }
-}
-
-fn wheel_keys_accel(i: u64) -> i32 {
-  let r = if i == 0 {
-     1 // FIXME: deal with this somehow, MK_WHEEL_DELTA
-  } else if i >= MK_WHEEL_TIME_TO_MAX {
-      MK_MAX_SPEED * MK_WHEEL_DELTA 
-  } else {
-      let action = MK_WHEEL_DELTA as f32 * 
-          MK_WHEEL_MAX_SPEED as f32 * ((i as f32 / MK_WHEEL_TIME_TO_MAX as f32).powf((1000.0 + MK_CURVE as f32) / 1000.0));
-      action.floor() as i32
-  };
-  if r <= 0 { 1 } else { r }
-}
-
-/*
- * Kinetic movement  acceleration algorithm
- *
- *  current speed = I + A * T/50 + A * 0.5 * T^2 | maximum B
- *
- * T: time since the mouse movement started
- * E: mouse events per second (set through MOUSEKEY_INTERVAL, UHK sends 250, the
- *    pro micro on my Signum 3.0 sends only 125!)
- * I: initial speed at time 0
- * A: acceleration
- * B: base mouse travel speed
- */
-/*fn kinetic_action(t: SystemTime) -> i32 {
-    let time_elapsed =  t.elapsed().unwrap().as_millis() as f32 / 10.0; // 10ms resolution 
-
-    let mut speed   = MOUSEKEY_INITIAL_SPEED + MOUSEKEY_MOVE_DELTA * time_elapsed + MOUSEKEY_MOVE_DELTA * 0.5 * time_elapsed * time_elapsed;
-
-    speed = speed.clamp(1000.0, MOUSEKEY_BASE_SPEED);
-
-    speed /= 1000.0 / MOUSEKEY_INTERVAL as f32;
-
-    speed.floor() as i32
-
-}*/
-
-
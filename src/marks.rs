@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use evdev::Key;
+
+// $XDG_STATE_HOME/kmouse/marks, with the usual ~/.local/state fallback
+pub fn state_file() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(&std::env::var_os("HOME")?).join(".local").join("state"),
+    };
+    Some(dir.join("kmouse").join("marks"))
+}
+
+// Pointer positions saved under the key that named them, vim style. The state file has
+// one mark per line, `KEY_A 640 360`, and gets rewritten on every change so the marks
+// survive restarts.
+pub struct Marks {
+    path: Option<PathBuf>,
+    marks: HashMap<Key, (u32, u32)>,
+}
+
+impl Marks {
+    // A missing or unreadable file just means no marks, lines that don't parse are skipped.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let mut marks = HashMap::new();
+        if let Some(text) = path.as_ref().and_then(|p| std::fs::read_to_string(p).ok()) {
+            for line in text.lines() {
+                let mut parts = line.split_whitespace();
+                let mark = (|| {
+                    let key = Key::from_str(parts.next()?).ok()?;
+                    Some((key, (parts.next()?.parse().ok()?, parts.next()?.parse().ok()?)))
+                })();
+                if let Some((key, position)) = mark {
+                    marks.insert(key, position);
+                }
+            }
+        }
+        Marks { path, marks }
+    }
+
+    pub fn get(&self, key: Key) -> Option<(u32, u32)> {
+        self.marks.get(&key).copied()
+    }
+
+    pub fn set(&mut self, key: Key, position: (u32, u32)) -> std::io::Result<()> {
+        self.marks.insert(key, position);
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut text = String::new();
+        for (key, (x, y)) in &self.marks {
+            text.push_str(&format!("{:?} {} {}\n", key, x, y));
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // written aside and renamed over, so a crash can't leave half a file
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, text)?;
+        std::fs::rename(&tmp, path)
+    }
+}
@@ -0,0 +1,230 @@
+use std::time::{Duration, Instant};
+
+use crate::accel::{self, AccelProfile};
+use crate::bindings::Direction;
+use crate::config::{OnTurn, Opposite};
+
+// The held direction keys of one kind of motion (pointer or wheel), combined into a single
+// velocity vector. Distances come out in fractional units along x (right positive) and
+// y (down positive), the caller turns them into events.
+pub struct Motion {
+    // when each direction went down, as a sequence number so `Opposite::Last` can tell
+    // which one came later
+    pressed: [Option<u64>; 4],
+    seq: u64,
+    started: Option<Instant>, // first key down, the repeat delay counts from here
+    ramp_start: Instant,      // acceleration counts from here
+    last_tick: Instant,
+    ticks: u64,
+    heading: (i32, i32),
+    pub opposite: Opposite,
+    pub on_turn: OnTurn,
+}
+
+fn index(direction: Direction) -> usize {
+    match direction {
+        Direction::Left => 0,
+        Direction::Right => 1,
+        Direction::Up => 2,
+        Direction::Down => 3,
+    }
+}
+
+impl Motion {
+    pub fn new(opposite: Opposite, on_turn: OnTurn) -> Self {
+        let now = Instant::now();
+        Motion {
+            pressed: [None; 4],
+            seq: 0,
+            started: None,
+            ramp_start: now,
+            last_tick: now,
+            ticks: 0,
+            heading: (0, 0),
+            opposite,
+            on_turn,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.started.is_some()
+    }
+
+    pub fn set(&mut self, direction: Direction, down: bool) {
+        self.set_at(direction, down, Instant::now());
+    }
+
+    fn set_at(&mut self, direction: Direction, down: bool, now: Instant) {
+        let slot = &mut self.pressed[index(direction)];
+        if down {
+            if slot.is_none() {
+                self.seq += 1;
+                *slot = Some(self.seq);
+            }
+        } else {
+            *slot = None;
+        }
+        if self.pressed.iter().all(Option::is_none) {
+            self.started = None;
+            self.heading = (0, 0);
+        } else if self.started.is_none() {
+            self.started = Some(now);
+            self.ramp_start = now;
+            self.last_tick = now;
+            self.ticks = 0;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pressed = [None; 4];
+        self.started = None;
+        self.heading = (0, 0);
+    }
+
+    fn axis(&self, negative: Direction, positive: Direction) -> i32 {
+        match (self.pressed[index(negative)], self.pressed[index(positive)]) {
+            (Some(_), None) => -1,
+            (None, Some(_)) => 1,
+            (Some(n), Some(p)) => match self.opposite {
+                Opposite::Cancel => 0,
+                Opposite::Last => if p > n { 1 } else { -1 },
+            },
+            (None, None) => 0,
+        }
+    }
+
+    // Motion for this tick, or None when there is nothing to move: no key held, opposite
+    // keys cancelling out, or still inside the repeat delay.
+    pub fn tick(&mut self, profile: &dyn AccelProfile, delay: Duration) -> Option<(f32, f32)> {
+        self.tick_at(profile, delay, Instant::now())
+    }
+
+    // tick and set with the time passed in, for the tests
+    fn tick_at(&mut self, profile: &dyn AccelProfile, delay: Duration, now: Instant) -> Option<(f32, f32)> {
+        let dt = now - self.last_tick;
+        self.last_tick = now;
+        let started = self.started?;
+
+        let heading = (self.axis(Direction::Left, Direction::Right), self.axis(Direction::Up, Direction::Down));
+        if heading != self.heading {
+            if self.on_turn == OnTurn::Reset && self.heading != (0, 0) {
+                self.ramp_start = now;
+            }
+            self.heading = heading;
+        }
+        if heading == (0, 0) || (self.ticks > 0 && now - started <= delay) {
+            return None;
+        }
+
+        let (x, y) = (heading.0 as f32, heading.1 as f32);
+        let len = x.hypot(y);
+        let mut d = accel::distance(profile, now - self.ramp_start, dt);
+        // the very first event of a press moves at least one unit along each axis, so a
+        // tap nudges, diagonals included
+        if self.ticks == 0 {
+            d = d.max(len);
+        }
+        self.ticks += 1;
+        Some((d * x / len, d * y / len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accel::{Constant, PiecewiseLinear};
+
+    const DELAY: Duration = Duration::from_millis(30);
+
+    // 1000 units per second, 10 per 10ms tick
+    const SPEED: Constant = Constant { speed: 1000.0 };
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3
+    }
+
+    #[test]
+    fn repeat_delay_after_the_first_event() {
+        let t = Instant::now();
+        let mut motion = Motion::new(Opposite::Cancel, OnTurn::Keep);
+        motion.set_at(Direction::Right, true, t);
+        // a tap moves at least one unit right away
+        assert_eq!(motion.tick_at(&SPEED, DELAY, t), Some((1.0, 0.0)));
+        assert_eq!(motion.tick_at(&SPEED, DELAY, t + ms(10)), None);
+        assert_eq!(motion.tick_at(&SPEED, DELAY, t + ms(30)), None);
+        assert!(close(motion.tick_at(&SPEED, DELAY, t + ms(40)).unwrap(), (10.0, 0.0)));
+        motion.set_at(Direction::Right, false, t + ms(45));
+        assert_eq!(motion.tick_at(&SPEED, DELAY, t + ms(50)), None);
+        assert!(!motion.is_active());
+    }
+
+    #[test]
+    fn diagonals_move_at_the_same_speed() {
+        let t = Instant::now();
+        let mut motion = Motion::new(Opposite::Cancel, OnTurn::Keep);
+        motion.set_at(Direction::Right, true, t);
+        motion.set_at(Direction::Up, true, t);
+        // at least one unit along each axis for the first event
+        let (x, y) = motion.tick_at(&SPEED, Duration::ZERO, t).unwrap();
+        assert!(close((x, y), (1.0, -1.0)));
+        let (x, y) = motion.tick_at(&SPEED, Duration::ZERO, t + ms(10)).unwrap();
+        let side = 10.0 / 2f32.sqrt();
+        assert!(close((x, y), (side, -side)));
+    }
+
+    #[test]
+    fn opposite_keys_cancel() {
+        let t = Instant::now();
+        let mut motion = Motion::new(Opposite::Cancel, OnTurn::Keep);
+        motion.set_at(Direction::Left, true, t);
+        motion.set_at(Direction::Right, true, t);
+        assert_eq!(motion.tick_at(&SPEED, Duration::ZERO, t), None);
+        // the other axis still moves
+        motion.set_at(Direction::Down, true, t);
+        assert!(close(motion.tick_at(&SPEED, Duration::ZERO, t + ms(10)).unwrap(), (0.0, 10.0)));
+    }
+
+    #[test]
+    fn opposite_keys_last_wins() {
+        let t = Instant::now();
+        let mut motion = Motion::new(Opposite::Last, OnTurn::Keep);
+        motion.set_at(Direction::Right, true, t);
+        motion.set_at(Direction::Left, true, t);
+        assert_eq!(motion.tick_at(&SPEED, Duration::ZERO, t), Some((-1.0, 0.0)));
+        // letting go of the later one goes back to the earlier one
+        motion.set_at(Direction::Left, false, t);
+        assert!(close(motion.tick_at(&SPEED, Duration::ZERO, t + ms(10)).unwrap(), (10.0, 0.0)));
+    }
+
+    // 0 units per second at first, 1000 after a second held
+    fn ramp() -> PiecewiseLinear {
+        PiecewiseLinear::by_time(vec![(0.0, 0.0), (1000.0, 1000.0)])
+    }
+
+    fn turn(on_turn: OnTurn) -> (f32, f32) {
+        let t = Instant::now();
+        let mut motion = Motion::new(Opposite::Cancel, on_turn);
+        motion.set_at(Direction::Right, true, t);
+        motion.tick_at(&ramp(), Duration::ZERO, t);
+        motion.tick_at(&ramp(), Duration::ZERO, t + ms(990));
+        motion.set_at(Direction::Down, true, t + ms(995));
+        motion.set_at(Direction::Right, false, t + ms(995));
+        motion.tick_at(&ramp(), Duration::ZERO, t + ms(1000)).unwrap()
+    }
+
+    #[test]
+    fn turning_keeps_the_speed() {
+        // 10ms at 1000 per second
+        assert!(close(turn(OnTurn::Keep), (0.0, 10.0)));
+    }
+
+    #[test]
+    fn turning_resets_the_ramp() {
+        // back at the start of the ramp
+        assert!(close(turn(OnTurn::Reset), (0.0, 0.0)));
+    }
+}
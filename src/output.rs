@@ -0,0 +1,167 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+use evdev::{AbsInfo, AbsoluteAxisType, AttributeSet, EventType, InputEvent, Key, RelativeAxisType, UinputAbsSetup};
+
+use crate::bindings::{Button, Warp};
+use crate::config::ScreenConfig;
+
+// The virtual device, wrapped so that every button goes back up however main ends: a
+// signal, an emit or read error passed up with `?`, or a panic unwinding through it.
+// Otherwise the compositor sees BTN_LEFT stuck down until the device disappears, e.g.
+// after getting killed in the middle of a drag lock.
+//
+// Drop alone isn't enough: a panic while unwinding, or a build with panic = "abort",
+// ends the process without running it. So a panic hook releases the buttons as well,
+// through a weak handle to the device that goes dead once the Output is dropped.
+//
+// Motion needs no cleanup, the relative axes carry no state, it stops with the ticks.
+pub struct Output {
+    device: Arc<Mutex<VirtualDevice>>,
+    name: String,
+    absolute: Option<Absolute>,
+}
+
+// A second device with ABS_X/ABS_Y spanning the screen, which jumps the pointer to a spot
+// instead of flying it there. It has to be separate, libinput treats a device with
+// absolute axes as a tablet-like pointer and would ignore the relative ones.
+struct Absolute {
+    device: VirtualDevice,
+    screen: ScreenConfig,
+    // the kernel drops absolute events that repeat the last value, even when the pointer
+    // has moved away in the meantime, so we need to know what that was
+    last: Option<(i32, i32)>,
+    // Where we think the pointer is: the last warp plus all relative motion since. Only
+    // as good as the compositor passes our motion through unscaled, and unknown until
+    // the first warp.
+    position: Option<(i32, i32)>,
+}
+
+fn absolute_device(name: &str, screen: &ScreenConfig) -> std::io::Result<VirtualDevice> {
+    let x = UinputAbsSetup::new(
+        AbsoluteAxisType::ABS_X,
+        AbsInfo::new(0, 0, screen.width as i32 - 1, 0, 0, 0),
+    );
+    let y = UinputAbsSetup::new(
+        AbsoluteAxisType::ABS_Y,
+        AbsInfo::new(0, 0, screen.height as i32 - 1, 0, 0, 0),
+    );
+    // never pressed, but without a button udev doesn't tag it as a mouse and libinput
+    // leaves it alone
+    let mut keys = AttributeSet::<Key>::new();
+    keys.insert(Key::BTN_LEFT);
+    VirtualDeviceBuilder::new()?
+        .name(&format!("{} absolute", name))
+        .with_absolute_axis(&x)?
+        .with_absolute_axis(&y)?
+        .with_keys(&keys)?
+        .build()
+}
+
+// Releasing a button that isn't down is harmless, the kernel drops key events that
+// don't change the state, so there is no need to track what is held.
+fn release_events() -> Vec<InputEvent> {
+    Button::ALL.iter().map(|b| InputEvent::new(EventType::KEY, b.key().0, 0)).collect()
+}
+
+// Poisoning doesn't matter, nothing panics while holding the lock half way through
+fn lock(device: &Mutex<VirtualDevice>) -> MutexGuard<'_, VirtualDevice> {
+    device.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn release_on_panic(device: &Arc<Mutex<VirtualDevice>>) {
+    let handle = Arc::downgrade(device);
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // try_lock, the panic may have come from inside an emit
+        if let Some(Ok(mut device)) = handle.upgrade().as_deref().map(Mutex::try_lock) {
+            if let Err(e) = device.emit(&release_events()) {
+                eprintln!("Releasing the buttons failed: {}", e);
+            }
+        }
+        previous(info);
+    }));
+}
+
+impl Output {
+    pub fn new(device: VirtualDevice, name: &str) -> Self {
+        let device = Arc::new(Mutex::new(device));
+        release_on_panic(&device);
+        Output { device, name: name.to_string(), absolute: None }
+    }
+
+    // Creates, replaces or drops the absolute device to match the configured screen.
+    pub fn set_screen(&mut self, screen: Option<&ScreenConfig>) -> std::io::Result<()> {
+        if self.absolute.as_ref().map(|a| &a.screen) == screen {
+            return Ok(());
+        }
+        self.absolute = None;
+        if let Some(screen) = screen {
+            let mut device = absolute_device(&self.name, screen)?;
+            for path in device.enumerate_dev_nodes_blocking()? {
+                println!("Absolute device available as {}", path?.display());
+            }
+            self.absolute = Some(Absolute { device, screen: screen.clone(), last: None, position: None });
+        }
+        Ok(())
+    }
+
+    pub fn warp(&mut self, warp: Warp) -> std::io::Result<()> {
+        let absolute = match &mut self.absolute {
+            Some(absolute) => absolute,
+            // the config validation rejects warp bindings without a screen
+            None => return Ok(()),
+        };
+        let (x, y) = warp.position(absolute.screen.width, absolute.screen.height);
+        let (x, y) = (x as i32, y as i32);
+        let abs = |x: i32, y: i32| {
+            [
+                InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_X.0, x),
+                InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_Y.0, y),
+            ]
+        };
+        // warping to the same spot twice, step one pixel aside first so it isn't dropped
+        if absolute.last == Some((x, y)) {
+            let aside = if x > 0 { x - 1 } else { x + 1 };
+            absolute.device.emit(&abs(aside, y))?;
+        }
+        absolute.device.emit(&abs(x, y))?;
+        absolute.last = Some((x, y));
+        absolute.position = Some((x, y));
+        Ok(())
+    }
+
+    pub fn position(&self) -> Option<(u32, u32)> {
+        let (x, y) = self.absolute.as_ref()?.position?;
+        Some((x as u32, y as u32))
+    }
+
+    pub fn emit(&mut self, events: &[InputEvent]) -> std::io::Result<()> {
+        if let Some(Absolute { screen, position: Some((x, y)), .. }) = &mut self.absolute {
+            for e in events.iter().filter(|e| e.event_type() == EventType::RELATIVE) {
+                match RelativeAxisType(e.code()) {
+                    RelativeAxisType::REL_X => *x = x.saturating_add(e.value()).clamp(0, screen.width as i32 - 1),
+                    RelativeAxisType::REL_Y => *y = y.saturating_add(e.value()).clamp(0, screen.height as i32 - 1),
+                    _ => {}
+                }
+            }
+        }
+        lock(&self.device).emit(events)
+    }
+
+    pub fn button(&mut self, button: Button, value: i32) -> std::io::Result<()> {
+        self.emit(&[InputEvent::new(EventType::KEY, button.key().0, value)])
+    }
+
+    pub fn release_all(&mut self) -> std::io::Result<()> {
+        self.emit(&release_events())
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Err(e) = self.release_all() {
+            eprintln!("Releasing the buttons failed: {}", e);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+use futures_util::StreamExt;
+use inotify::{EventStream, Inotify, WatchMask};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+// Tells the main loop when to re-read the config: on SIGHUP, or when the config file
+// is written. Editors usually save by renaming a temp file over the original, which
+// drops a watch on the file itself, so we watch its directory and filter by name.
+pub struct ConfigWatcher {
+    hangup: Signal,
+    events: Option<EventStream<Vec<u8>>>,
+    file_name: Option<OsString>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: Option<&Path>) -> std::io::Result<Self> {
+        let hangup = signal(SignalKind::hangup())?;
+        let mut watcher = ConfigWatcher { hangup, events: None, file_name: None };
+        if let Some(path) = path {
+            let dir = match path.parent() {
+                Some(d) if !d.as_os_str().is_empty() => d,
+                _ => Path::new("."),
+            };
+            let inotify = Inotify::init()?;
+            // not CREATE, that fires before anything is written and the empty file would
+            // load as the defaults
+            inotify.watches().add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)?;
+            watcher.events = Some(inotify.into_event_stream(vec![0; 4096])?);
+            watcher.file_name = path.file_name().map(Into::into);
+        }
+        Ok(watcher)
+    }
+
+    pub async fn changed(&mut self) {
+        let ConfigWatcher { hangup, events, file_name } = self;
+        let file_changed = async {
+            while let Some(stream) = events {
+                match stream.next().await {
+                    Some(Ok(ev)) if ev.name.as_ref() == file_name.as_ref() => return,
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => {
+                        eprintln!("Watching the config file failed, only SIGHUP will reload it: {}", e);
+                        *events = None;
+                    }
+                    None => *events = None,
+                }
+            }
+            std::future::pending().await
+        };
+        tokio::select! {
+            _ = hangup.recv() => {},
+            _ = file_changed => {},
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::time::{Duration, Instant};
+
+use evdev::{EventType, InputEvent, Key};
+
+// Output events that have to go out later, like the release half of a click or the steps
+// of a click sequence. The main loop waits on `due` next to its timers instead of
+// sleeping, so motion and incoming keys keep flowing while a click completes.
+#[derive(Default)]
+pub struct Scheduler {
+    // kept sorted by time, events scheduled for the same instant stay in insertion order
+    queue: Vec<(Instant, Vec<InputEvent>)>,
+}
+
+impl Scheduler {
+    pub fn at(&mut self, at: Instant, events: Vec<InputEvent>) {
+        let i = self.queue.partition_point(|(t, _)| *t <= at);
+        self.queue.insert(i, (at, events));
+    }
+
+    // When the last pending event with this code goes out, so a new sequence for the same
+    // button can queue up behind it instead of interleaving.
+    pub fn last(&self, code: u16) -> Option<Instant> {
+        self.queue
+            .iter()
+            .rev()
+            .find(|(_, events)| events.iter().any(|e| e.code() == code))
+            .map(|(t, _)| *t)
+    }
+
+    // `count` clicks of `key`, each holding it for `press` with `gap` in between. Queued
+    // behind a click of the same button that is still in flight.
+    pub fn clicks(&mut self, key: Key, count: u8, press: Duration, gap: Duration) {
+        let now = Instant::now();
+        let mut t = match self.last(key.0) {
+            Some(last) => last.max(now) + gap,
+            None => now,
+        };
+        for i in 0..count {
+            if i > 0 {
+                t += gap;
+            }
+            self.at(t, vec![InputEvent::new(EventType::KEY, key.0, 1)]);
+            t += press;
+            self.at(t, vec![InputEvent::new(EventType::KEY, key.0, 0)]);
+        }
+    }
+
+    // Drops every pending event with this code, e.g. when the button gets released for
+    // good.
+    pub fn cancel(&mut self, code: u16) {
+        for (_, events) in self.queue.iter_mut() {
+            events.retain(|e| e.code() != code);
+        }
+        self.queue.retain(|(_, events)| !events.is_empty());
+    }
+
+    // Resolves once the earliest event is due and hands out everything due by then, one
+    // batch per scheduled entry, since each has to go out in its own report.
+    pub async fn due(&mut self) -> Vec<Vec<InputEvent>> {
+        match self.queue.first() {
+            Some((at, _)) => tokio::time::sleep_until((*at).into()).await,
+            None => std::future::pending().await,
+        }
+        let now = Instant::now();
+        let n = self.queue.partition_point(|(t, _)| *t <= now);
+        self.queue.drain(..n).map(|(_, events)| events).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEFT: Key = Key::BTN_LEFT;
+    const RIGHT: Key = Key::BTN_RIGHT;
+
+    fn key(key: Key, value: i32) -> InputEvent {
+        InputEvent::new(EventType::KEY, key.0, value)
+    }
+
+    // (milliseconds after the first entry, code, value) of everything queued
+    fn timeline(scheduler: &Scheduler) -> Vec<(u128, u16, i32)> {
+        let start = scheduler.queue[0].0;
+        let entries = scheduler.queue.iter().flat_map(|(t, events)| {
+            events.iter().map(move |e| ((*t - start).as_millis(), e.code(), e.value()))
+        });
+        entries.collect()
+    }
+
+    #[test]
+    fn queue_is_sorted_by_time() {
+        let t = Instant::now();
+        let mut scheduler = Scheduler::default();
+        scheduler.at(t + Duration::from_millis(20), vec![key(LEFT, 0)]);
+        scheduler.at(t, vec![key(LEFT, 1)]);
+        scheduler.at(t + Duration::from_millis(10), vec![key(RIGHT, 1)]);
+        // same instant, goes after the one already there
+        scheduler.at(t + Duration::from_millis(10), vec![key(RIGHT, 0)]);
+        assert_eq!(
+            timeline(&scheduler),
+            [(0, LEFT.0, 1), (10, RIGHT.0, 1), (10, RIGHT.0, 0), (20, LEFT.0, 0)]
+        );
+    }
+
+    #[test]
+    fn last_finds_the_latest_event_of_a_code() {
+        let t = Instant::now();
+        let mut scheduler = Scheduler::default();
+        assert_eq!(scheduler.last(LEFT.0), None);
+        scheduler.at(t, vec![key(LEFT, 1)]);
+        scheduler.at(t + Duration::from_millis(30), vec![key(LEFT, 0)]);
+        scheduler.at(t + Duration::from_millis(50), vec![key(RIGHT, 1)]);
+        assert_eq!(scheduler.last(LEFT.0), Some(t + Duration::from_millis(30)));
+    }
+
+    #[test]
+    fn click_queues_behind_one_in_flight() {
+        let (press, gap) = (Duration::from_millis(32), Duration::from_millis(20));
+        let mut scheduler = Scheduler::default();
+        scheduler.clicks(LEFT, 1, press, gap);
+        scheduler.clicks(LEFT, 1, press, gap);
+        assert_eq!(
+            timeline(&scheduler),
+            [(0, LEFT.0, 1), (32, LEFT.0, 0), (52, LEFT.0, 1), (84, LEFT.0, 0)]
+        );
+    }
+
+    #[test]
+    fn other_buttons_dont_wait() {
+        let (press, gap) = (Duration::from_millis(32), Duration::from_millis(20));
+        let mut scheduler = Scheduler::default();
+        scheduler.clicks(LEFT, 1, press, gap);
+        scheduler.clicks(RIGHT, 1, press, gap);
+        let right: Vec<_> = timeline(&scheduler).into_iter().filter(|(_, code, _)| *code == RIGHT.0).collect();
+        // pressed as good as right away, not after the left click
+        assert!(right[0].0 < 5);
+        assert_eq!(right[1].0 - right[0].0, 32);
+    }
+
+    #[test]
+    fn double_click_sequence() {
+        let (press, gap) = (Duration::from_millis(32), Duration::from_millis(20));
+        let mut scheduler = Scheduler::default();
+        scheduler.clicks(LEFT, 2, press, gap);
+        assert_eq!(
+            timeline(&scheduler),
+            [(0, LEFT.0, 1), (32, LEFT.0, 0), (52, LEFT.0, 1), (84, LEFT.0, 0)]
+        );
+    }
+
+    #[test]
+    fn triple_click_sequence() {
+        let (press, gap) = (Duration::from_millis(10), Duration::from_millis(40));
+        let mut scheduler = Scheduler::default();
+        scheduler.clicks(RIGHT, 3, press, gap);
+        assert_eq!(
+            timeline(&scheduler),
+            [
+                (0, RIGHT.0, 1),
+                (10, RIGHT.0, 0),
+                (50, RIGHT.0, 1),
+                (60, RIGHT.0, 0),
+                (100, RIGHT.0, 1),
+                (110, RIGHT.0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn double_click_behind_a_double_click() {
+        let (press, gap) = (Duration::from_millis(30), Duration::from_millis(30));
+        let mut scheduler = Scheduler::default();
+        scheduler.clicks(LEFT, 2, press, gap);
+        scheduler.clicks(LEFT, 2, press, gap);
+        let presses: Vec<u128> = timeline(&scheduler).into_iter().filter(|e| e.2 == 1).map(|e| e.0).collect();
+        assert_eq!(presses, [0, 60, 120, 180]);
+    }
+
+    #[test]
+    fn cancel_drops_only_that_code() {
+        let t = Instant::now();
+        let mut scheduler = Scheduler::default();
+        scheduler.at(t, vec![key(LEFT, 1), key(RIGHT, 1)]);
+        scheduler.at(t + Duration::from_millis(10), vec![key(LEFT, 0)]);
+        scheduler.at(t + Duration::from_millis(20), vec![key(RIGHT, 0)]);
+        scheduler.cancel(LEFT.0);
+        assert_eq!(timeline(&scheduler), [(0, RIGHT.0, 1), (20, RIGHT.0, 0)]);
+        // no empty entries left behind to wake up for
+        assert_eq!(scheduler.queue.len(), 2);
+        scheduler.cancel(RIGHT.0);
+        assert!(scheduler.queue.is_empty());
+    }
+}
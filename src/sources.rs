@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Duration;
+
+use evdev::{Device, EventStream, InputEvent};
+use futures_util::Stream;
+use inotify::{Inotify, WatchDescriptor, WatchMask};
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::config::DeviceConfig;
+use crate::devices;
+
+pub enum SourceEvent {
+    Input(usize, InputEvent),
+    Added(usize, String),                    // id and name of a device that (re)appeared
+    Removed(usize, String, std::io::Error), // and one that went away, e.g. KMonad restarting
+}
+
+struct Source {
+    id: usize,
+    path: PathBuf,
+    name: String,
+    stream: EventStream,
+}
+
+// The input devices KMouse reads from, merged into one stream of events tagged with the
+// id of the device they came from. Each poll starts after the device that delivered
+// last, so one busy keyboard can't starve the other.
+//
+// Devices come and go: a read error drops the device, and /dev/input is watched so that
+// any device matching the rule gets picked up when it shows up, at startup too, when
+// KMonad isn't running yet. Ids aren't reused, a device that comes back gets a new one.
+//
+// Without inotify, e.g. at its watch limit, it looks for them every few seconds instead.
+//
+// KMouse's own virtual devices never count, even when the rule matches them, reading
+// back what we emit would only feed the loop.
+pub struct Sources {
+    rule: DeviceConfig,
+    own: [String; 2],
+    sources: Vec<Source>,
+    next: usize,
+    next_id: usize,
+    pending: VecDeque<SourceEvent>,
+    watch: Option<Watch>,
+    poll: Option<Interval>, // when watching doesn't work
+    grabbed: bool,
+}
+
+struct Watch {
+    events: inotify::EventStream<Vec<u8>>,
+    dirs: Vec<(WatchDescriptor, PathBuf)>,
+}
+
+const POLL: Duration = Duration::from_secs(2);
+
+fn watch() -> std::io::Result<Watch> {
+    let inotify = Inotify::init()?;
+    // udev makes the node readable and adds the by-id and by-path links only after it
+    // appears, so attribute changes and the link directories count as well
+    let mask = WatchMask::CREATE | WatchMask::ATTRIB | WatchMask::MOVED_TO;
+    let mut dirs = vec![(inotify.watches().add("/dev/input", mask)?, PathBuf::from("/dev/input"))];
+    for dir in ["/dev/input/by-id", "/dev/input/by-path"] {
+        // only there while some device has such a link
+        if let Ok(wd) = inotify.watches().add(dir, mask) {
+            dirs.push((wd, PathBuf::from(dir)));
+        }
+    }
+    Ok(Watch { events: inotify.into_event_stream(vec![0; 4096])?, dirs })
+}
+
+impl Sources {
+    // `virtual_name` is what our own devices are called, see output.rs
+    pub fn new(rule: DeviceConfig, virtual_name: &str) -> Self {
+        let mut sources = Sources {
+            rule,
+            own: [virtual_name.to_string(), format!("{} absolute", virtual_name)],
+            sources: vec![],
+            next: 0,
+            next_id: 0,
+            pending: VecDeque::new(),
+            watch: None,
+            poll: None,
+            grabbed: false,
+        };
+        match watch() {
+            Ok(watch) => sources.watch = Some(watch),
+            Err(e) => sources.poll_instead(e),
+        }
+        sources.scan();
+        sources
+    }
+
+    fn poll_instead(&mut self, e: std::io::Error) {
+        eprintln!("Watching /dev/input failed, looking for devices every {}s instead: {}", POLL.as_secs(), e);
+        self.watch = None;
+        let mut poll = tokio::time::interval(POLL);
+        poll.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        self.poll = Some(poll);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    // Attaches every matching device that isn't yet, queueing an Added for each.
+    fn scan(&mut self) {
+        for (path, device) in devices::find(&self.rule) {
+            if !self.sources.iter().any(|s| s.path == path) {
+                self.attach(path, device);
+            }
+        }
+    }
+
+    // Same for the one device a node or link that just changed leads to, so that udev
+    // touching some other device doesn't reopen them all.
+    fn rescan(&mut self, path: &Path) {
+        let path = match path.canonicalize() {
+            Ok(path) => path,
+            // gone again, or a link to something that isn't there yet
+            Err(_) => return,
+        };
+        if self.sources.iter().any(|s| s.path == path) {
+            return;
+        }
+        if let Some(device) = devices::open(&self.rule, &path) {
+            self.attach(path, device);
+        }
+    }
+
+    fn attach(&mut self, path: PathBuf, device: Device) {
+        let name = device.name().unwrap_or("Unnamed device").to_string();
+        if self.own.contains(&name) {
+            return;
+        }
+        let mut stream = match device.into_event_stream() {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Reading from {} failed: {}", path.display(), e);
+                return;
+            }
+        };
+        if self.grabbed {
+            if let Err(e) = stream.device_mut().grab() {
+                eprintln!("Grabbing {} failed: {}", name, e);
+            }
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.push_back(SourceEvent::Added(id, name.clone()));
+        self.sources.push(Source { id, path, name, stream });
+    }
+
+    pub async fn next(&mut self) -> SourceEvent {
+        poll_fn(|cx| {
+            // the paths that changed, None when the queue overflowed and any could have, or
+            // when it's time to poll
+            let mut changed: Vec<Option<PathBuf>> = vec![];
+            while let Some(watch) = &mut self.watch {
+                match Pin::new(&mut watch.events).poll_next(cx) {
+                    Poll::Ready(Some(Ok(event))) => {
+                        let dir = watch.dirs.iter().find(|(wd, _)| *wd == event.wd).map(|(_, dir)| dir);
+                        let path = dir.zip(event.name).map(|(dir, name)| dir.join(name));
+                        if !changed.contains(&path) {
+                            changed.push(path);
+                        }
+                    }
+                    Poll::Ready(Some(Err(e))) => self.poll_instead(e),
+                    Poll::Ready(None) => self.poll_instead(std::io::ErrorKind::UnexpectedEof.into()),
+                    Poll::Pending => break,
+                }
+            }
+            while let Some(poll) = &mut self.poll {
+                match poll.poll_tick(cx) {
+                    Poll::Ready(_) if changed.contains(&None) => {}
+                    Poll::Ready(_) => changed.push(None),
+                    Poll::Pending => break,
+                }
+            }
+            for path in changed {
+                match path {
+                    Some(path) => self.rescan(&path),
+                    None => self.scan(),
+                }
+            }
+            if let Some(event) = self.pending.pop_front() {
+                return Poll::Ready(event);
+            }
+
+            let n = self.sources.len();
+            for i in (0..n).map(|i| (self.next + i) % n) {
+                match self.sources[i].stream.poll_event(cx) {
+                    Poll::Ready(Ok(event)) => {
+                        self.next = (i + 1) % n;
+                        return Poll::Ready(SourceEvent::Input(self.sources[i].id, event));
+                    }
+                    Poll::Ready(Err(e)) => {
+                        let source = self.sources.remove(i);
+                        self.next = 0;
+                        return Poll::Ready(SourceEvent::Removed(source.id, source.name, e));
+                    }
+                    Poll::Pending => {}
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    // Keeps the keys from reaching anybody else, for the modes that type into KMouse.
+    // Devices attached in the meantime get grabbed too.
+    pub fn grab(&mut self) -> std::io::Result<()> {
+        self.grabbed = true;
+        self.sources.iter_mut().try_for_each(|s| s.stream.device_mut().grab())
+    }
+
+    pub fn ungrab(&mut self) -> std::io::Result<()> {
+        self.grabbed = false;
+        self.sources.iter_mut().try_for_each(|s| s.stream.device_mut().ungrab())
+    }
+}